@@ -0,0 +1,23 @@
+use std::fs;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ireal_url::{parse_url, parse_url_with, parse_urls, ParseOptions};
+
+fn parse(c: &mut Criterion) {
+    let url = fs::read_to_string("src/tests/data/jazz1460.url").unwrap();
+    c.bench_function("parse jazz1460", |b| {
+        b.iter(|| parse_url(black_box(&url)).unwrap())
+    });
+    let lenient = ParseOptions { lenient: true };
+    c.bench_function("parse jazz1460 leniently", |b| {
+        b.iter(|| parse_url_with(black_box(&url), &lenient).unwrap())
+    });
+    // Run with `--features rayon` to compare.
+    let urls = vec![url; 8];
+    c.bench_function("parse 8 copies of jazz1460 as a batch", |b| {
+        b.iter(|| parse_urls(black_box(&urls), &ParseOptions::default()))
+    });
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
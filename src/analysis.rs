@@ -0,0 +1,363 @@
+use crate::{
+    notation::{degree, ChordStyle, ROMAN},
+    parse::Music,
+    types::{AlteredNotes, Chord, Flavor, Key, Note, Number},
+    Song,
+};
+
+/// The broad sound of a chord, which is what harmonic analysis cares about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Quality {
+    Major,
+    Minor,
+    Dominant,
+    HalfDiminished,
+    Diminished,
+    Augmented,
+}
+
+impl Chord {
+    /// Pitch class of the root, or None for N.C. and bass-only chords.
+    pub fn root_pitch_class(&self) -> Option<u8> {
+        match self {
+            Chord::NC => None,
+            Chord::Some { root, .. } => root.pitch_class(),
+        }
+    }
+
+    pub fn quality(&self) -> Option<Quality> {
+        let (flavor, altered_notes) = match self {
+            Chord::NC => return None,
+            Chord::Some {
+                flavor,
+                altered_notes,
+                ..
+            } => (flavor, altered_notes),
+        };
+        Some(match flavor {
+            Flavor::Major(_) | Flavor::SixthNinth => Quality::Major,
+            Flavor::Minor(_) | Flavor::MinorMajor(_) | Flavor::MinorSixthNinth => Quality::Minor,
+            Flavor::HalfDiminished(_) => Quality::HalfDiminished,
+            Flavor::Diminished(_) | Flavor::DiminishedMajor(_) => Quality::Diminished,
+            Flavor::Augmented(Some(_)) => Quality::Dominant,
+            Flavor::Augmented(None) => Quality::Augmented,
+            Flavor::Dominant(Some(
+                Number::Seven | Number::Nine | Number::Eleven | Number::Thirteen,
+            )) => Quality::Dominant,
+            // Triads, sixths and add chords. An altered triad is still
+            // heard as a dominant.
+            Flavor::Dominant(_) => {
+                if altered_notes.contains(&AlteredNotes::Alt) {
+                    Quality::Dominant
+                } else {
+                    Quality::Major
+                }
+            }
+        })
+    }
+}
+
+fn extension(n: &Number) -> u8 {
+    match n {
+        Number::Two | Number::Nine => 2,
+        Number::Three => 4,
+        Number::Five => 7,
+        Number::Six | Number::Thirteen => 9,
+        Number::Seven => 10,
+        Number::Eleven => 5,
+    }
+}
+
+impl Chord {
+    /// The chord tones as semitones above the root, lowest first. Based on
+    /// the usual jazz reading of each symbol; extensions include the
+    /// seventh, so C9 is C E G Bb D.
+    pub fn intervals(&self) -> Vec<u8> {
+        let (flavor, altered_notes) = match self {
+            Chord::NC => return vec![],
+            Chord::Some {
+                flavor,
+                altered_notes,
+                ..
+            } => (flavor, altered_notes),
+        };
+        let (third, fifth, seventh) = match flavor {
+            Flavor::Major(_) => (4, 7, Some(11)),
+            Flavor::Minor(_) => (3, 7, Some(10)),
+            Flavor::MinorMajor(_) => (3, 7, Some(11)),
+            Flavor::HalfDiminished(_) => (3, 6, Some(10)),
+            Flavor::Diminished(_) => (3, 6, Some(9)),
+            Flavor::DiminishedMajor(_) => (3, 6, Some(11)),
+            Flavor::Augmented(_) => (4, 8, Some(10)),
+            Flavor::Dominant(_) => (4, 7, Some(10)),
+            Flavor::SixthNinth => (4, 7, None),
+            Flavor::MinorSixthNinth => (3, 7, None),
+        };
+        let number = match flavor {
+            Flavor::Major(n)
+            | Flavor::Minor(n)
+            | Flavor::MinorMajor(n)
+            | Flavor::HalfDiminished(n)
+            | Flavor::Diminished(n)
+            | Flavor::DiminishedMajor(n)
+            | Flavor::Augmented(n)
+            | Flavor::Dominant(n) => n.clone(),
+            Flavor::SixthNinth | Flavor::MinorSixthNinth => None,
+        };
+        let mut intervals = vec![0, third, fifth];
+        match (flavor, &number) {
+            (Flavor::SixthNinth | Flavor::MinorSixthNinth, _) => intervals.extend([9, 2]),
+            // "^", "h" and "-^" on their own still have their seventh, but a
+            // plain triad, "-", "o" and "+" don't.
+            (
+                Flavor::Major(None)
+                | Flavor::HalfDiminished(None)
+                | Flavor::MinorMajor(None)
+                | Flavor::DiminishedMajor(None),
+                _,
+            ) => intervals.extend(seventh),
+            (_, None) => {}
+            (_, Some(Number::Two)) => intervals.push(2),
+            (_, Some(Number::Three)) => {}
+            (_, Some(Number::Five)) => intervals.retain(|&i| i != third),
+            (_, Some(Number::Six)) => intervals.push(9),
+            (_, Some(n)) => {
+                intervals.extend(seventh);
+                if *n == Number::Eleven && matches!(flavor, Flavor::Dominant(_)) {
+                    // A dominant 11 leaves out the third.
+                    intervals.retain(|&i| i != third);
+                }
+                let top = extension(n);
+                if top != 10 {
+                    // Extensions stack thirds: a 13 chord includes the 9.
+                    if matches!(n, Number::Eleven | Number::Thirteen) {
+                        intervals.push(2);
+                    }
+                    intervals.push(top);
+                }
+            }
+        }
+        for altered in altered_notes {
+            match altered {
+                AlteredNotes::Flat(n) | AlteredNotes::Sharp(n) => {
+                    let natural = extension(n);
+                    let note = if matches!(altered, AlteredNotes::Flat(_)) {
+                        (natural + 11) % 12
+                    } else {
+                        (natural + 1) % 12
+                    };
+                    // An altered fifth or ninth replaces the natural one.
+                    if matches!(n, Number::Five | Number::Nine) {
+                        intervals.retain(|&i| i != natural);
+                    }
+                    intervals.push(note);
+                }
+                AlteredNotes::Add(n) => intervals.push(extension(n)),
+                AlteredNotes::Sus => {
+                    intervals.retain(|&i| i != 3 && i != 4);
+                    intervals.push(5);
+                }
+                AlteredNotes::Alt => {
+                    intervals.retain(|&i| i != 2 && i != 7 && i != 9);
+                    intervals.extend([1, 3, 6, 8]);
+                }
+                AlteredNotes::Custom(_) => {}
+            }
+        }
+        intervals.sort();
+        intervals.dedup();
+        intervals
+    }
+
+    /// The pitch classes of `intervals()`, transposed to the root.
+    pub fn pitch_classes(&self) -> Vec<u8> {
+        let root = match self.root_pitch_class() {
+            Some(root) => root,
+            None => return vec![],
+        };
+        let mut pitch_classes: Vec<u8> = self
+            .intervals()
+            .into_iter()
+            .map(|i| (root + i) % 12)
+            .collect();
+        pitch_classes.sort();
+        pitch_classes
+    }
+}
+
+/// Diatonic chord qualities on each semitone above the tonic.
+fn diatonic_qualities(minor: bool, interval: u8) -> &'static [Quality] {
+    use Quality::*;
+    if minor {
+        // Natural and harmonic minor.
+        match interval {
+            0 => &[Minor],
+            2 => &[HalfDiminished, Diminished],
+            3 => &[Major, Augmented],
+            5 => &[Minor],
+            7 => &[Dominant, Minor, Major],
+            8 => &[Major],
+            10 => &[Dominant, Major],
+            11 => &[Diminished],
+            _ => &[],
+        }
+    } else {
+        match interval {
+            0 => &[Major],
+            2 => &[Minor],
+            4 => &[Minor],
+            5 => &[Major],
+            7 => &[Dominant, Major],
+            9 => &[Minor],
+            11 => &[HalfDiminished],
+            _ => &[],
+        }
+    }
+}
+
+fn is_diatonic(key: &Key, interval: u8, quality: Quality) -> bool {
+    diatonic_qualities(key.minor, interval).contains(&quality)
+}
+
+/// What a chord is doing in the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HarmonicFunction {
+    /// Built from the notes of the key.
+    Diatonic,
+    /// The dominant of another diatonic chord, e.g. V7/ii. Holds the
+    /// interval in semitones from the tonic to the chord it points to.
+    SecondaryDominant(u8),
+    /// Taken from the parallel major or minor key, e.g. iv or bVII7 in a
+    /// major key.
+    Borrowed,
+    /// Anything else.
+    Chromatic,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChordAnalysis {
+    pub chord: Chord,
+    /// Index into `Music.written_bars`.
+    pub bar: usize,
+    pub beat: u32,
+    /// Roman numeral, e.g. "ii7", "V7/V", "bVII7", "iiø7".
+    pub roman: String,
+    /// Nashville number, e.g. "2m⁷", "5⁷/5".
+    pub nashville: String,
+    pub function: HarmonicFunction,
+}
+
+/// Label one chord with its function in `key`.
+pub fn analyze_chord(chord: &Chord, key: &Key) -> Option<(String, String, HarmonicFunction)> {
+    let tonic = key.tonic.pitch_class()?;
+    let interval = (chord.root_pitch_class()? + 12 - tonic) % 12;
+    let quality = chord.quality()?;
+
+    let function = if is_diatonic(key, interval, quality) {
+        HarmonicFunction::Diatonic
+    } else if let Some(target) = secondary_target(key, interval, quality) {
+        HarmonicFunction::SecondaryDominant(target)
+    } else if is_diatonic(
+        &Key {
+            tonic: key.tonic.clone(),
+            minor: !key.minor,
+        },
+        interval,
+        quality,
+    ) {
+        HarmonicFunction::Borrowed
+    } else {
+        HarmonicFunction::Chromatic
+    };
+
+    let (roman, nashville) = match function {
+        HarmonicFunction::SecondaryDominant(target) => {
+            let target_note = Note::from_pitch_class(tonic + target, key.prefers_flats());
+            let target_key = Key {
+                tonic: target_note.clone(),
+                minor: false,
+            };
+            let (accidental, number) = degree(key, &target_note)?;
+            let numeral = ROMAN[number as usize - 1];
+            let qualities = diatonic_qualities(key.minor, target);
+            let numeral =
+                if qualities.contains(&Quality::Minor) && !qualities.contains(&Quality::Dominant) {
+                    numeral.to_lowercase()
+                } else {
+                    numeral.to_string()
+                };
+            (
+                format!(
+                    "{}/{}{}",
+                    chord.styled(&ChordStyle::Roman(target_key.clone())),
+                    accidental,
+                    numeral
+                ),
+                format!(
+                    "{}/{}{}",
+                    chord.styled(&ChordStyle::Nashville(target_key)),
+                    accidental,
+                    number
+                ),
+            )
+        }
+        _ => (
+            chord.styled(&ChordStyle::Roman(key.clone())).to_string(),
+            chord
+                .styled(&ChordStyle::Nashville(key.clone()))
+                .to_string(),
+        ),
+    };
+    Some((roman, nashville, function))
+}
+
+/* A dominant chord a fifth above a diatonic major or minor chord (other than
+ * the tonic) is that chord's secondary dominant. The key's own V is
+ * diatonic, so it never gets here. */
+fn secondary_target(key: &Key, interval: u8, quality: Quality) -> Option<u8> {
+    if quality != Quality::Dominant {
+        return None;
+    }
+    let target = (interval + 5) % 12;
+    if target == 0 {
+        return None;
+    }
+    let qualities = diatonic_qualities(key.minor, target);
+    if qualities.contains(&Quality::Major)
+        || qualities.contains(&Quality::Minor)
+        || qualities.contains(&Quality::Dominant)
+    {
+        Some(target)
+    } else {
+        None
+    }
+}
+
+impl Music {
+    /// Label every chord with its roman numeral, Nashville number and
+    /// function in `key`. N.C. and bass-only chords are skipped.
+    pub fn analyze(&self, key: &Key) -> Vec<ChordAnalysis> {
+        self.timeline()
+            .into_iter()
+            .filter_map(|timed| {
+                let (roman, nashville, function) = analyze_chord(&timed.chord, key)?;
+                Some(ChordAnalysis {
+                    chord: timed.chord,
+                    bar: timed.bar,
+                    beat: timed.beat,
+                    roman,
+                    nashville,
+                    function,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Song {
+    /// `Music::analyze` relative to the song's key.
+    pub fn analyze(&self) -> Result<Vec<ChordAnalysis>, String> {
+        let key: Key = self.key.parse()?;
+        Ok(self.music.analyze(&key))
+    }
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+
+/// Where a D.C. or D.S. sends the player back to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jump {
+    /// D.C., back to the top.
+    DaCapo,
+    /// D.S., back to the segno.
+    DalSegno,
+}
+
+/// How far to play after jumping back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Until {
+    /// Play to the coda sign, then skip to the coda.
+    Coda,
+    /// Play to the bar marked Fine.
+    Fine,
+    /// Play through to the numbered ending.
+    Ending(u32),
+    /// Play to the end.
+    End,
+}
+
+/// What a comment in a chart says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Annotation {
+    /// A D.C. or D.S. direction, with the comment's text since it often
+    /// says more, like "After solos, D.S. al Coda".
+    Direction {
+        jump: Jump,
+        until: Until,
+        text: String,
+    },
+    Fine {
+        text: String,
+    },
+    /// How many times to play a repeat, from comments like "3x" or
+    /// "Repeat 4 times".
+    RepeatCount {
+        count: u32,
+        text: String,
+    },
+    Text(String),
+}
+
+/// Where a comment is drawn. iReal charts shift comments up or down with a
+/// `*NN` prefix and right with leading `XyQ` spacers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub offset: Option<u32>,
+    pub indent: usize,
+}
+
+// "3x", "(4xs)", "x3", "Repeat 3x" or "Repeat 4 times".
+fn repeat_count(text: &str) -> Option<u32> {
+    let text = text.to_lowercase();
+    let text = text.trim_start_matches('(').trim_end_matches(')').trim();
+    let text = text.strip_prefix("repeat").unwrap_or(text).trim();
+    let count = if let Some(n) = text.strip_prefix('x') {
+        n
+    } else {
+        ["times", "xs", "x"]
+            .iter()
+            .find_map(|suffix| text.strip_suffix(suffix))?
+    };
+    count.trim().parse().ok().filter(|&n| n > 0)
+}
+
+fn direction(text: &str) -> Option<Annotation> {
+    let upper = text.to_uppercase();
+    let (jump, at) = match (upper.find("D.C."), upper.find("D.S.")) {
+        (Some(at), _) => (Jump::DaCapo, at),
+        (None, Some(at)) => (Jump::DalSegno, at),
+        (None, None) => return None,
+    };
+    let rest = upper[at + 4..].trim_start();
+    let rest = rest.strip_prefix("AL").unwrap_or(rest).trim_start();
+    let until = if rest.starts_with("CODA") {
+        Until::Coda
+    } else if rest.starts_with("FINE") {
+        Until::Fine
+    } else {
+        // "2nd ending", "3rd End.", "1st Ending".
+        let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+        match digits.parse() {
+            Ok(n) if rest[digits.len()..].starts_with(['S', 'N', 'R', 'T']) => Until::Ending(n),
+            _ => Until::End,
+        }
+    };
+    Some(Annotation::Direction {
+        jump,
+        until,
+        text: text.to_string(),
+    })
+}
+
+impl Annotation {
+    /// Read the text of a `<...>` comment.
+    pub fn parse(text: &str) -> (Annotation, Position) {
+        let mut position = Position::default();
+        let mut text = text;
+        if let Some(rest) = text.strip_prefix('*') {
+            let digits = rest
+                .get(..2)
+                .filter(|d| d.bytes().all(|b| b.is_ascii_digit()));
+            if let Some(digits) = digits {
+                position.offset = digits.parse().ok();
+                text = &rest[2..];
+            }
+        }
+        while let Some(rest) = text.strip_prefix("XyQ") {
+            position.indent += 1;
+            text = rest;
+        }
+        let text = text.trim();
+
+        let annotation = if text.eq_ignore_ascii_case("fine") {
+            Annotation::Fine {
+                text: text.to_string(),
+            }
+        } else if let Some(n) = repeat_count(text) {
+            Annotation::RepeatCount {
+                count: n,
+                text: text.to_string(),
+            }
+        } else if let Some(direction) = direction(text) {
+            direction
+        } else {
+            Annotation::Text(text.to_string())
+        };
+        (annotation, position)
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Annotation::Direction { text, .. }
+            | Annotation::Fine { text }
+            | Annotation::RepeatCount { text, .. }
+            | Annotation::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{
+    parse::{self, Music},
+    tokenize::Token,
+    types::{Chord, TimeSignature},
+    Song,
+};
+
+/* The time signatures iReal can write. */
+const TIME_SIGNATURES: [(u32, u32); 13] = [
+    (2, 4),
+    (3, 4),
+    (4, 4),
+    (5, 4),
+    (6, 4),
+    (7, 4),
+    (2, 2),
+    (3, 2),
+    (5, 8),
+    (6, 8),
+    (7, 8),
+    (9, 8),
+    (12, 8),
+];
+
+/* Builds the iReal music string one element at a time, then parses it back
+ * into a `Music`. Going through the text keeps `Music.raw` in sync with the
+ * written bars, so the result can be encoded into a URL as-is. */
+
+#[derive(Debug, Clone)]
+pub struct MusicBuilder {
+    text: String,
+    time_signature: TimeSignature,
+    // Whether `time_signature` has been written into `text` yet.
+    time_signature_written: bool,
+    bar_number: usize,
+    beats_used: u32,
+    cells_used: u32,
+    bar_has_content: bool,
+    repeat_open: bool,
+    error: Option<String>,
+}
+
+impl MusicBuilder {
+    pub fn new() -> Self {
+        MusicBuilder {
+            text: String::new(),
+            time_signature: TimeSignature { top: 4, bottom: 4 },
+            time_signature_written: false,
+            bar_number: 1,
+            beats_used: 0,
+            cells_used: 0,
+            bar_has_content: false,
+            repeat_open: false,
+            error: None,
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(format!("Bar {}: {}", self.bar_number, message));
+        }
+    }
+
+    fn write_time_signature(&mut self) {
+        if !self.time_signature_written {
+            let ts = &self.time_signature;
+            let token = Token::TimeSignature(ts.top, ts.bottom);
+            self.text.push_str(&token.to_string());
+            self.time_signature_written = true;
+        }
+    }
+
+    /* A bar is drawn as 4 cells, unless its beats don't fit into 4 cells
+     * evenly, as in 3/4, when it gets a cell per beat so that every
+     * duration can be written. */
+    fn cells_per_bar(&self) -> u32 {
+        match self.time_signature.top {
+            2 | 4 => 4,
+            top => top,
+        }
+    }
+
+    /// Change the time signature, starting with the current bar. Only the
+    /// signatures iReal has are allowed.
+    pub fn time_signature(mut self, top: u32, bottom: u32) -> Self {
+        if !TIME_SIGNATURES.contains(&(top, bottom)) {
+            self.fail(format!("Invalid time signature {}/{}", top, bottom));
+            return self;
+        }
+        if self.bar_has_content {
+            self.fail("Time signature must come before the bar's chords".to_string());
+            return self;
+        }
+        self.time_signature = TimeSignature { top, bottom };
+        self.time_signature_written = false;
+        self.write_time_signature();
+        self
+    }
+
+    /// Start a rehearsal section, e.g. "A" or "B". iReal only supports a
+    /// single character.
+    pub fn section(mut self, name: &str) -> Self {
+        if name.chars().count() != 1 {
+            self.fail(format!("Section marker must be one character: {:?}", name));
+            return self;
+        }
+        if self.bar_has_content {
+            self.fail("Section marker must come at the beginning of a bar".to_string());
+            return self;
+        }
+        self.text.push('*');
+        self.text.push_str(name);
+        self
+    }
+
+    pub fn repeat_start(mut self) -> Self {
+        if self.repeat_open {
+            self.fail("Repeat started inside another repeat".to_string());
+            return self;
+        }
+        if self.bar_has_content {
+            self.fail("Repeat must start at the beginning of a bar".to_string());
+            return self;
+        }
+        self.repeat_open = true;
+        self.text.push('{');
+        self
+    }
+
+    /// Numbered ending, placed at the start of a bar.
+    pub fn ending(mut self, n: u32) -> Self {
+        if !(1..=9).contains(&n) {
+            self.fail(format!("Invalid ending number {}", n));
+            return self;
+        }
+        if self.bar_has_content {
+            self.fail("Ending must come at the beginning of a bar".to_string());
+            return self;
+        }
+        self.text.push_str(&format!("N{}", n));
+        self
+    }
+
+    /// Segno sign, at the beginning of a bar.
+    pub fn segno(mut self) -> Self {
+        if self.bar_has_content {
+            self.fail("Segno must come at the beginning of a bar".to_string());
+            return self;
+        }
+        self.text.push('S');
+        self
+    }
+
+    pub fn coda(mut self) -> Self {
+        self.text.push('Q');
+        self
+    }
+
+    /// Mark the current bar as the one the tune ends on, the last time
+    /// through.
+    pub fn ending_measure(mut self) -> Self {
+        self.text.push('U');
+        self
+    }
+
+    /// Add extra space above the line the bar falls on, at the beginning of
+    /// a bar. iReal charts use it at the start of a line.
+    pub fn vertical_space(mut self) -> Self {
+        if self.bar_has_content {
+            self.fail("Vertical space must come at the beginning of a bar".to_string());
+            return self;
+        }
+        self.text.push('Y');
+        self
+    }
+
+    pub fn comment(mut self, text: &str) -> Self {
+        if text.contains('>') {
+            self.fail(format!("Comment may not contain '>': {:?}", text));
+            return self;
+        }
+        self.text.push_str(&format!("<{}>", text));
+        self
+    }
+
+    /// Add a chord lasting `beats` beats of the current time signature.
+    pub fn chord(mut self, chord: Chord, beats: u32) -> Self {
+        self.write_time_signature();
+        let beats_per_bar = self.time_signature.top;
+        if beats == 0 {
+            self.fail(format!("{} has no duration", chord));
+            return self;
+        }
+        if self.beats_used + beats > beats_per_bar {
+            self.fail(format!(
+                "{} overfills the bar ({} + {} beats in {})",
+                chord, self.beats_used, beats, self.time_signature
+            ));
+            return self;
+        }
+        // Each chord takes one cell, and the cells after it are left blank
+        // for as long as the chord lasts.
+        let cells = beats * self.cells_per_bar() / beats_per_bar;
+        match chord {
+            Chord::NC => self.text.push('n'),
+            _ => self.text.push_str(&chord.to_string()),
+        }
+        for _ in 1..cells {
+            self.text.push(' ');
+        }
+        self.beats_used += beats;
+        self.cells_used += cells;
+        self.bar_has_content = true;
+        self
+    }
+
+    /// Repeat the previous bar.
+    pub fn repeat_bar(mut self) -> Self {
+        if self.bar_has_content {
+            self.fail("Bar repeat must be the only thing in a bar".to_string());
+            return self;
+        }
+        self.write_time_signature();
+        // The sign takes the middle of three cells, and the bar is padded
+        // from there.
+        self.text.push_str(" x ");
+        self.beats_used = self.time_signature.top;
+        self.cells_used = 3;
+        self.bar_has_content = true;
+        self
+    }
+
+    fn end_bar(mut self, line: &str) -> Self {
+        if !self.bar_has_content {
+            self.fail("Bar is empty".to_string());
+            return self;
+        }
+        while self.cells_used < self.cells_per_bar() {
+            self.text.push(' ');
+            self.cells_used += 1;
+        }
+        self.text.push_str(line);
+        self.bar_number += 1;
+        self.beats_used = 0;
+        self.cells_used = 0;
+        self.bar_has_content = false;
+        self
+    }
+
+    /// End the current bar with a single barline.
+    pub fn bar(self) -> Self {
+        self.end_bar("|")
+    }
+
+    /// Start a double barline section, at the beginning of a bar.
+    pub fn double_bar_start(mut self) -> Self {
+        if self.bar_has_content {
+            self.fail("Double barline must start at the beginning of a bar".to_string());
+            return self;
+        }
+        self.text.push('[');
+        self
+    }
+
+    /// End the current bar with a closing double barline.
+    pub fn double_bar_end(self) -> Self {
+        self.end_bar("]")
+    }
+
+    /// End the current bar with a repeat sign.
+    pub fn repeat_end(mut self) -> Self {
+        if !self.repeat_open {
+            self.fail("Repeat ended without being started".to_string());
+            return self;
+        }
+        self.repeat_open = false;
+        self.end_bar("}")
+    }
+
+    /// End the current bar with the final barline.
+    pub fn final_bar(self) -> Self {
+        self.end_bar("Z")
+    }
+
+    /// The music text built so far, in iReal's (unscrambled) notation.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn build(mut self) -> Result<Music, String> {
+        if self.bar_has_content {
+            self = self.bar();
+        }
+        if self.repeat_open {
+            self.fail("Repeat is never ended".to_string());
+        }
+        if self.bar_number == 1 {
+            self.fail("Music has no bars".to_string());
+        }
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        Ok(parse::parse_music(&self.text)?)
+    }
+}
+
+impl Default for MusicBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SongBuilder {
+    title: String,
+    composer: String,
+    style: String,
+    key: String,
+    transpose: String,
+    music: Option<Music>,
+    comp_style: String,
+    bpm: u32,
+    repeats: String,
+}
+
+impl SongBuilder {
+    pub fn new(title: &str) -> Self {
+        SongBuilder {
+            title: title.to_string(),
+            composer: "Composer Unknown".to_string(),
+            style: "Medium Swing".to_string(),
+            key: "C".to_string(),
+            transpose: String::new(),
+            music: None,
+            comp_style: String::new(),
+            bpm: 0,
+            repeats: "0".to_string(),
+        }
+    }
+
+    /// iReal writes composers last name first, e.g. "Monk Thelonious".
+    pub fn composer(mut self, composer: &str) -> Self {
+        self.composer = composer.to_string();
+        self
+    }
+
+    pub fn style(mut self, style: &str) -> Self {
+        self.style = style.to_string();
+        self
+    }
+
+    pub fn key(mut self, key: &str) -> Self {
+        self.key = key.to_string();
+        self
+    }
+
+    pub fn transpose(mut self, transpose: &str) -> Self {
+        self.transpose = transpose.to_string();
+        self
+    }
+
+    pub fn comp_style(mut self, comp_style: &str) -> Self {
+        self.comp_style = comp_style.to_string();
+        self
+    }
+
+    pub fn bpm(mut self, bpm: u32) -> Self {
+        self.bpm = bpm;
+        self
+    }
+
+    pub fn repeats(mut self, repeats: &str) -> Self {
+        self.repeats = repeats.to_string();
+        self
+    }
+
+    pub fn music(mut self, music: Music) -> Self {
+        self.music = Some(music);
+        self
+    }
+
+    pub fn build(self) -> Result<Song, String> {
+        if self.title.is_empty() {
+            return Err("Song has no title".to_string());
+        }
+        for (name, value) in [
+            ("title", &self.title),
+            ("composer", &self.composer),
+            ("style", &self.style),
+            ("key", &self.key),
+            ("transpose", &self.transpose),
+            ("comp_style", &self.comp_style),
+            ("repeats", &self.repeats),
+        ] {
+            if value.contains('=') {
+                return Err(format!("Song {} may not contain '=': {:?}", name, value));
+            }
+        }
+        let music = match self.music {
+            Some(music) => music,
+            None => return Err(format!("Song {:?} has no music", self.title)),
+        };
+        Ok(Song {
+            title: self.title,
+            composer: self.composer,
+            style: self.style,
+            key: self.key,
+            transpose: self.transpose,
+            music,
+            comp_style: self.comp_style,
+            bpm: self.bpm,
+            repeats: self.repeats,
+            warnings: vec![],
+        })
+    }
+}
@@ -0,0 +1,483 @@
+use crate::{
+    annotation::Annotation,
+    layout::{BarLayout, Barline, Layout, CELLS_PER_BAR, CELLS_PER_ROW},
+    notation::ChordStyle,
+    parse::{CellContent, Music},
+    tokenize::Width,
+    transpose::Part,
+    types::Chord,
+    Song,
+};
+
+/// Page width in points, the width of US letter paper.
+pub const PAGE_WIDTH: f64 = 612.0;
+const MARGIN: f64 = 36.0;
+const CELL: f64 = (PAGE_WIDTH - 2.0 * MARGIN) / CELLS_PER_ROW as f64;
+const ROW_HEIGHT: f64 = 56.0;
+// Added above a row for each of iReal's vertical spacers.
+const ROW_SPACE: f64 = 14.0;
+// Where the barlines of a row start and end, below the top of the row.
+const STAFF_TOP: f64 = 16.0;
+const STAFF_BOTTOM: f64 = 46.0;
+const HEADER_HEIGHT: f64 = 84.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    Start,
+    Middle,
+    End,
+}
+
+/// A vector shape in a drawing. Coordinates are in points, with y growing
+/// down the page.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Line {
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+        width: f64,
+    },
+    /// An outlined rectangle.
+    Rect {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+    Circle {
+        x: f64,
+        y: f64,
+        radius: f64,
+        filled: bool,
+    },
+    Polyline {
+        points: Vec<(f64, f64)>,
+        width: f64,
+    },
+    /// Text with its baseline at `y`.
+    Text {
+        x: f64,
+        y: f64,
+        size: f64,
+        text: String,
+        anchor: Anchor,
+        bold: bool,
+        italic: bool,
+    },
+}
+
+/// A chart drawn as vector shapes, ready to be written out as SVG or PDF.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drawing {
+    pub width: f64,
+    pub height: f64,
+    pub shapes: Vec<Shape>,
+}
+
+impl Drawing {
+    fn line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, width: f64) {
+        self.shapes.push(Shape::Line {
+            x1,
+            y1,
+            x2,
+            y2,
+            width,
+        });
+    }
+
+    fn text(&mut self, x: f64, y: f64, size: f64, text: &str, anchor: Anchor) {
+        self.shapes.push(Shape::Text {
+            x,
+            y,
+            size,
+            text: text.to_string(),
+            anchor,
+            bold: false,
+            italic: false,
+        });
+    }
+
+    fn styled_text(&mut self, x: f64, y: f64, size: f64, text: &str, bold: bool, italic: bool) {
+        self.shapes.push(Shape::Text {
+            x,
+            y,
+            size,
+            text: text.to_string(),
+            anchor: Anchor::Start,
+            bold,
+            italic,
+        });
+    }
+
+    fn dot(&mut self, x: f64, y: f64, radius: f64) {
+        self.shapes.push(Shape::Circle {
+            x,
+            y,
+            radius,
+            filled: true,
+        });
+    }
+}
+
+/* Helvetica's character widths in thousandths of the font size, for the
+ * printable ASCII characters. Other characters are taken to be as wide as
+ * a digit. */
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// Approximate width of text in Helvetica.
+pub(crate) fn text_width(text: &str, size: f64) -> f64 {
+    let units: u32 = text
+        .chars()
+        .map(|c| match c as u32 {
+            32..=126 => HELVETICA_WIDTHS[c as usize - 32] as u32,
+            _ => 556,
+        })
+        .sum();
+    units as f64 * size / 1000.0
+}
+
+/// Write the flats and sharps after a note's letter as music glyphs.
+pub(crate) fn accidentals(note: &str) -> String {
+    let mut chars = note.chars();
+    let letter: String = chars.next().into_iter().collect();
+    letter
+        + &chars
+            .as_str()
+            .replace('b', "\u{266d}")
+            .replace('#', "\u{266f}")
+}
+
+/* A chord symbol split into the root, the part written small and raised, and
+ * the bass note. */
+pub(crate) fn chord_parts(chord: &Chord) -> (String, String, String) {
+    match chord {
+        Chord::NC => ("N.C.".to_string(), String::new(), String::new()),
+        Chord::Some {
+            root, bass_note, ..
+        } => {
+            let plain = chord.styled(&ChordStyle::Plain).to_string();
+            let root = root.to_string();
+            let bass = bass_note
+                .as_ref()
+                .map(|b| format!("/{}", b))
+                .unwrap_or_default();
+            let written_bass = bass_note
+                .as_ref()
+                .map(|b| format!("/{}", accidentals(&b.to_string())))
+                .unwrap_or_default();
+            let mut suffix = plain.as_str();
+            if root != "W" {
+                suffix = suffix.strip_prefix(&root).unwrap_or(suffix);
+            }
+            suffix = suffix.strip_suffix(&bass).unwrap_or(suffix);
+            let root = if root == "W" { String::new() } else { root };
+            (accidentals(&root), suffix.to_string(), written_bass)
+        }
+    }
+}
+
+fn draw_chord(drawing: &mut Drawing, chord: &Chord, width: &Width, x: f64, y: f64, room: f64) {
+    let (root, suffix, bass) = chord_parts(chord);
+    let (mut big, mut small) = match width {
+        Width::Wide => (15.0, 10.0),
+        Width::Narrow => (11.0, 8.0),
+    };
+    let needed = text_width(&root, big) + text_width(&suffix, small) + text_width(&bass, big);
+    if needed > room {
+        let scale = (room / needed).max(0.6);
+        big *= scale;
+        small *= scale;
+    }
+    let mut x = x;
+    drawing.text(x, y, big, &root, Anchor::Start);
+    x += text_width(&root, big);
+    if !suffix.is_empty() {
+        drawing.text(x, y - big * 0.35, small, &suffix, Anchor::Start);
+        x += text_width(&suffix, small);
+    }
+    if !bass.is_empty() {
+        drawing.text(x, y, big, &bass, Anchor::Start);
+    }
+}
+
+/* Barlines are drawn as a sequence of strokes from left to right. `align` is
+ * which part of the sequence sits on `x`: 0 the left end, 1 the right. */
+fn draw_barline(drawing: &mut Drawing, barline: Barline, x: f64, align: f64, top: f64) {
+    enum Stroke {
+        Thin,
+        Thick,
+        Dots,
+    }
+    use Stroke::*;
+    let strokes: &[(f64, Stroke)] = match barline {
+        Barline::Single => &[(0.0, Thin)],
+        Barline::Double => &[(0.0, Thin), (3.0, Thin)],
+        Barline::Final => &[(0.0, Thin), (4.0, Thick)],
+        Barline::RepeatStart => &[(0.0, Thick), (3.5, Thin), (7.0, Dots)],
+        Barline::RepeatEnd => &[(0.0, Dots), (3.5, Thin), (7.0, Thick)],
+        Barline::RepeatBoth => &[
+            (0.0, Dots),
+            (3.5, Thin),
+            (7.0, Thick),
+            (10.5, Thin),
+            (14.0, Dots),
+        ],
+    };
+    let span = strokes.last().map_or(0.0, |s| s.0);
+    let left = x - span * align;
+    let (y1, y2) = (top + STAFF_TOP, top + STAFF_BOTTOM);
+    for (offset, stroke) in strokes {
+        let x = left + offset;
+        match stroke {
+            Thin => drawing.line(x, y1, x, y2, 1.0),
+            Thick => drawing.line(x, y1, x, y2, 3.0),
+            Dots => {
+                drawing.dot(x, y1 + 10.0, 1.5);
+                drawing.dot(x, y2 - 10.0, 1.5);
+            }
+        }
+    }
+}
+
+fn draw_segno(drawing: &mut Drawing, x: f64, y: f64) {
+    drawing.styled_text(x - 4.0, y + 5.0, 14.0, "S", false, true);
+    drawing.line(x - 5.0, y + 5.0, x + 5.0, y - 7.0, 1.0);
+    drawing.dot(x - 5.0, y - 1.0, 1.2);
+    drawing.dot(x + 5.0, y - 1.0, 1.2);
+}
+
+fn draw_coda(drawing: &mut Drawing, x: f64, y: f64) {
+    drawing.shapes.push(Shape::Circle {
+        x,
+        y,
+        radius: 5.0,
+        filled: false,
+    });
+    drawing.line(x - 7.5, y, x + 7.5, y, 1.0);
+    drawing.line(x, y - 7.5, x, y + 7.5, 1.0);
+}
+
+fn draw_fermata(drawing: &mut Drawing, x: f64, y: f64) {
+    let points = (0..=8)
+        .map(|i| {
+            let angle = std::f64::consts::PI * i as f64 / 8.0;
+            (x - 6.0 * angle.cos(), y - 5.0 * angle.sin())
+        })
+        .collect();
+    drawing.shapes.push(Shape::Polyline { points, width: 1.0 });
+    drawing.dot(x, y - 1.5, 1.2);
+}
+
+fn draw_simile(drawing: &mut Drawing, x: f64, top: f64) {
+    let (y1, y2) = (top + STAFF_TOP + 8.0, top + STAFF_BOTTOM - 8.0);
+    drawing.line(x - 6.0, y2, x + 6.0, y1, 2.5);
+    drawing.dot(x - 5.0, y1 + 2.0, 2.0);
+    drawing.dot(x + 5.0, y2 - 2.0, 2.0);
+}
+
+fn draw_bar(drawing: &mut Drawing, bar: &BarLayout, top: f64) {
+    let x = MARGIN + bar.first_cell as f64 * CELL;
+    let end = x + bar.cells.len() as f64 * CELL;
+    let baseline = top + STAFF_BOTTOM - 9.0;
+
+    draw_barline(
+        drawing,
+        bar.start,
+        x,
+        if bar.first_cell == 0 { 0.0 } else { 0.5 },
+        top,
+    );
+
+    let mut indent = 0.0;
+    if let Some(ts) = &bar.time_signature {
+        let x = x + 8.0;
+        let y = top + STAFF_TOP;
+        drawing.styled_text(x, y + 13.0, 11.0, &ts.top.to_string(), true, false);
+        drawing.styled_text(x, y + 26.0, 11.0, &ts.bottom.to_string(), true, false);
+        indent = 10.0;
+    }
+    if let Some(section) = &bar.section {
+        drawing.shapes.push(Shape::Rect {
+            x: x - 8.0,
+            y: top,
+            width: 15.0,
+            height: 15.0,
+        });
+        drawing.styled_text(x - 4.5, top + 12.0, 12.0, section, true, false);
+    }
+    if bar.segno {
+        draw_segno(drawing, x + 18.0, top + 7.0);
+    }
+    if bar.coda {
+        draw_coda(drawing, x + 18.0, top + 7.0);
+    }
+    if bar.fermata {
+        draw_fermata(drawing, x + CELL / 2.0, top + STAFF_TOP + 4.0);
+    }
+    if bar.ending_measure {
+        drawing.shapes.push(Shape::Text {
+            x: end - 3.0,
+            y: top + 11.0,
+            size: 9.0,
+            text: "END".to_string(),
+            anchor: Anchor::End,
+            bold: true,
+            italic: false,
+        });
+    }
+    if let Some(n) = bar.ending {
+        let right = x + bar.ending_bars as f64 * CELLS_PER_BAR as f64 * CELL;
+        let right = right.min(MARGIN + CELLS_PER_ROW as f64 * CELL) - 2.0;
+        drawing.line(x + 2.0, top + STAFF_TOP - 2.0, x + 2.0, top + 1.0, 1.0);
+        drawing.line(x + 2.0, top + 1.0, right, top + 1.0, 1.0);
+        drawing.text(x + 5.0, top + 11.0, 9.0, &format!("{}.", n), Anchor::Start);
+    }
+
+    match bar.simile {
+        1 => draw_simile(drawing, (x + end) / 2.0, top),
+        2 => {
+            let middle = (x + end) / 2.0;
+            drawing.line(middle, top + STAFF_TOP, middle, top + STAFF_BOTTOM, 1.0);
+            draw_simile(drawing, middle, top);
+            drawing.text(middle, top + STAFF_TOP - 2.0, 9.0, "2", Anchor::Middle);
+        }
+        _ => {}
+    }
+
+    for (i, cell) in bar.cells.iter().enumerate() {
+        let cell_x = x + i as f64 * CELL + if i == 0 { indent } else { 0.0 };
+        let next = bar.cells[i + 1..]
+            .iter()
+            .position(|c| c.content.is_some())
+            .map_or(bar.cells.len() - i, |p| p + 1);
+        let room = next as f64 * CELL - 6.0 - if i == 0 { indent } else { 0.0 };
+        match &cell.content {
+            Some(CellContent::Chord(chord, width)) => {
+                draw_chord(drawing, chord, width, cell_x + 4.0, baseline, room)
+            }
+            Some(CellContent::Slash) => {
+                let center = cell_x + CELL / 2.0;
+                drawing.line(
+                    center - 4.0,
+                    baseline + 2.0,
+                    center + 4.0,
+                    baseline - 12.0,
+                    2.0,
+                );
+            }
+            // Repeat signs are drawn across the whole bar.
+            _ => {}
+        }
+        if let Some(alternate) = &cell.alternate {
+            let (root, suffix, bass) = chord_parts(alternate);
+            let text = format!("{}{}{}", root, suffix, bass);
+            drawing.text(
+                cell_x + 4.0,
+                top + STAFF_TOP - 3.0,
+                8.0,
+                &text,
+                Anchor::Start,
+            );
+        }
+    }
+
+    for (i, comment) in bar.comments.iter().enumerate() {
+        let y = top + STAFF_BOTTOM + 8.0 + i as f64 * 8.0;
+        // Directions are for the whole band, so they stand out.
+        let direction = matches!(
+            comment,
+            Annotation::Direction { .. } | Annotation::Fine { .. }
+        );
+        drawing.styled_text(x + 3.0, y, 7.5, &comment.to_string(), direction, !direction);
+    }
+}
+
+fn layout_height(layout: &Layout) -> f64 {
+    layout
+        .rows
+        .iter()
+        .map(|row| ROW_HEIGHT + row.space as f64 * ROW_SPACE)
+        .sum()
+}
+
+fn draw_layout(drawing: &mut Drawing, layout: &Layout, top: f64) {
+    let mut y = top;
+    for row in &layout.rows {
+        y += row.space as f64 * ROW_SPACE;
+        for (i, bar) in row.bars.iter().enumerate() {
+            draw_bar(drawing, bar, y);
+            if row.shows_end(i) {
+                let x = MARGIN + (bar.first_cell + bar.cells.len()) as f64 * CELL;
+                draw_barline(drawing, bar.end, x, 1.0, y);
+            }
+        }
+        y += ROW_HEIGHT;
+    }
+}
+
+impl Music {
+    /// Draw the chart on iReal's grid.
+    pub fn draw(&self) -> Drawing {
+        let layout = self.layout();
+        let mut drawing = Drawing {
+            width: PAGE_WIDTH,
+            height: 2.0 * MARGIN + layout_height(&layout),
+            shapes: vec![],
+        };
+        draw_layout(&mut drawing, &layout, MARGIN);
+        drawing
+    }
+}
+
+impl Song {
+    /// Draw the chart with a header giving the title, composer, style, key
+    /// and tempo.
+    pub fn draw(&self) -> Drawing {
+        let layout = self.music.layout();
+        let mut drawing = Drawing {
+            width: PAGE_WIDTH,
+            height: HEADER_HEIGHT + MARGIN + layout_height(&layout),
+            shapes: vec![],
+        };
+        let right = PAGE_WIDTH - MARGIN;
+        drawing.shapes.push(Shape::Text {
+            x: PAGE_WIDTH / 2.0,
+            y: MARGIN + 12.0,
+            size: 22.0,
+            text: self.title.clone(),
+            anchor: Anchor::Middle,
+            bold: true,
+            italic: false,
+        });
+        drawing.text(right, MARGIN + 34.0, 11.0, &self.composer, Anchor::End);
+        let mut details = format!("({})", self.style);
+        if !self.key.is_empty() {
+            details.push_str(&format!("  Key: {}", accidentals(&self.key)));
+        }
+        if self.bpm > 0 {
+            details.push_str(&format!("  {} bpm", self.bpm));
+        }
+        drawing.text(MARGIN, MARGIN + 34.0, 11.0, &details, Anchor::Start);
+        draw_layout(&mut drawing, &layout, HEADER_HEIGHT);
+        drawing
+    }
+}
+
+impl Part {
+    /// Draw the song's chart with the part's name in the corner.
+    pub fn draw(&self) -> Drawing {
+        let mut drawing = self.song.draw();
+        if let Some(label) = self.instrument.label() {
+            drawing.text(MARGIN, MARGIN + 12.0, 10.0, &label, Anchor::Start);
+        }
+        drawing
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use crate::{
+    analysis::Quality,
+    parse::{Music, WrittenElement},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormKind {
+    AABA,
+    ABAC,
+    AB,
+    TwelveBarBlues,
+    /// AABA on I vi ii V, with a bridge of dominants a fifth apart.
+    RhythmChanges,
+    /// No section comes back.
+    ThroughComposed,
+    /// Any other arrangement of sections, e.g. "ABCA".
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// Letter in the form, assigned by comparing the chords of each section.
+    pub letter: char,
+    /// The section marker written in the chart, if any.
+    pub marker: Option<String>,
+    /// Index into `Music.playback_order()` where the section starts.
+    pub start: usize,
+    /// Length in measures.
+    pub bars: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Form {
+    pub kind: FormKind,
+    /// The sections of the form in the order they're played. Intros, verses
+    /// and codas are not included.
+    pub sections: Vec<Section>,
+}
+
+impl Form {
+    /// The letters of the sections, e.g. "AABA".
+    pub fn letters(&self) -> String {
+        self.sections.iter().map(|s| s.letter).collect()
+    }
+}
+
+// Sections at least this similar get the same letter.
+const SIMILAR: f64 = 0.75;
+
+type Signature = Vec<(u8, Quality)>;
+
+fn similarity(a: &[&Signature], b: &[&Signature]) -> f64 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    let same = a.iter().zip(b).filter(|(x, y)| x == y).count();
+    same as f64 / longest as f64
+}
+
+impl Music {
+    /// Work out the form of the tune from its section markers, repeats and
+    /// chords.
+    pub fn form(&self) -> Form {
+        // The root and quality of each chord in every written bar.
+        let mut signatures: HashMap<usize, Signature> = HashMap::new();
+        for timed in self.timeline() {
+            if let (Some(root), Some(quality)) =
+                (timed.chord.root_pitch_class(), timed.chord.quality())
+            {
+                signatures
+                    .entry(timed.bar)
+                    .or_default()
+                    .push((root, quality));
+            }
+        }
+        let empty = Signature::new();
+        let measures = |bar: usize| {
+            if self.written_bars[bar]
+                .elements
+                .contains(&WrittenElement::RepeatTwoMeasures)
+            {
+                2
+            } else {
+                1
+            }
+        };
+
+        // Split the played bars at section markers. Anything after the final
+        // barline is a coda.
+        let order = self.playback_order();
+        let mut spans: Vec<(Option<String>, usize, Vec<usize>)> = vec![];
+        for (position, &bar) in order.iter().enumerate() {
+            if position > 0 && self.written_bars[order[position - 1]].final_bar {
+                break;
+            }
+            let marker = self.written_bars[bar]
+                .elements
+                .iter()
+                .find_map(|element| match element {
+                    WrittenElement::SectionMarker(s) => Some(s.clone()),
+                    _ => None,
+                });
+            match (&marker, spans.last_mut()) {
+                (None, Some(span)) => span.2.push(bar),
+                _ => spans.push((marker, position, vec![bar])),
+            }
+        }
+        // Intros and verses aren't part of the form.
+        spans.retain(|span| !matches!(span.0.as_deref(), Some("i") | Some("v")));
+
+        // Without markers, guess at 8 bar sections.
+        if spans.len() == 1 {
+            let (_, start, bars) = spans.pop().unwrap();
+            let length = if bars.len().is_multiple_of(8) && bars.len() > 8 {
+                8
+            } else {
+                bars.len()
+            };
+            for (i, chunk) in bars.chunks(length.max(1)).enumerate() {
+                spans.push((None, start + i * length, chunk.to_vec()));
+            }
+        }
+
+        let mut sections: Vec<Section> = vec![];
+        let mut contents: Vec<Vec<&Signature>> = vec![];
+        for (marker, start, bars) in &spans {
+            let content: Vec<&Signature> = bars
+                .iter()
+                .map(|bar| signatures.get(bar).unwrap_or(&empty))
+                .collect();
+            let letter = sections
+                .iter()
+                .zip(&contents)
+                .find(|(_, other)| similarity(&content, other) >= SIMILAR)
+                .map(|(section, _)| section.letter)
+                .unwrap_or_else(|| {
+                    // Past Z, further sections all get Z, and the form is
+                    // left as `Other`.
+                    let used = sections.iter().map(|s| s.letter).max();
+                    used.map_or('A', |c| if c < 'Z' { (c as u8 + 1) as char } else { 'Z' })
+                });
+            sections.push(Section {
+                letter,
+                marker: marker.clone(),
+                start: *start,
+                bars: bars.iter().map(|&bar| measures(bar)).sum(),
+            });
+            contents.push(content);
+        }
+
+        let letters: String = sections.iter().map(|s| s.letter).collect();
+        let all_bars: Vec<&Signature> = spans
+            .iter()
+            .flat_map(|span| span.2.iter())
+            .map(|bar| signatures.get(bar).unwrap_or(&empty))
+            .collect();
+        let kind = if is_blues(&all_bars) {
+            FormKind::TwelveBarBlues
+        } else if letters == "AABA" && is_rhythm_changes(&contents) {
+            FormKind::RhythmChanges
+        } else {
+            match letters.as_str() {
+                "AABA" => FormKind::AABA,
+                "ABAC" => FormKind::ABAC,
+                "AB" | "AABB" => FormKind::AB,
+                _ if sections.len() > 1
+                    && sections.iter().enumerate().all(|(i, s)| {
+                        sections[..i].iter().all(|other| other.letter != s.letter)
+                    }) =>
+                {
+                    FormKind::ThroughComposed
+                }
+                _ => FormKind::Other(letters),
+            }
+        };
+        Form { kind, sections }
+    }
+}
+
+fn first_root(bar: Option<&&Signature>) -> Option<u8> {
+    bar.and_then(|signature| signature.first())
+        .map(|chord| chord.0)
+}
+
+/* A 12 bar blues, possibly repeated: I in bar 1, IV in bar 5, V or ii in bar
+ * 9 and back to I in bar 11. */
+fn is_blues(bars: &[&Signature]) -> bool {
+    if bars.is_empty() || !bars.len().is_multiple_of(12) {
+        return false;
+    }
+    bars.chunks(12).all(|chorus| {
+        let tonic = match first_root(chorus.first()) {
+            Some(tonic) => tonic,
+            None => return false,
+        };
+        let at = |bar: usize| first_root(chorus.get(bar)).map(|root| (root + 12 - tonic) % 12);
+        at(4) == Some(5) && matches!(at(8), Some(7) | Some(2)) && at(10) == Some(0)
+    })
+}
+
+/* The bridge of rhythm changes is III7 VI7 II7 V7, two bars each. */
+fn is_rhythm_changes(sections: &[Vec<&Signature>]) -> bool {
+    let (a, bridge) = (&sections[0], &sections[2]);
+    if a.len() != 8 || bridge.len() != 8 {
+        return false;
+    }
+    let tonic = match first_root(a.first()) {
+        Some(tonic) => tonic,
+        None => return false,
+    };
+    [4, 9, 2, 7].iter().enumerate().all(|(i, interval)| {
+        bridge[i * 2]
+            .first()
+            .map(|&(root, quality)| {
+                quality == Quality::Dominant && (root + 12 - tonic) % 12 == *interval
+            })
+            .unwrap_or(false)
+    })
+}
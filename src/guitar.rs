@@ -0,0 +1,432 @@
+use std::fmt::Write;
+
+use crate::{
+    parse::Music,
+    svg::escape,
+    types::Chord,
+    voicings::{movement, tones},
+};
+
+// Shapes above this fret aren't offered.
+const MAX_FRET: u8 = 15;
+// Fretted notes in a shape must fit within this many frets.
+const MAX_SPAN: u8 = 3;
+
+/// Open string pitches as MIDI notes, lowest string first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tuning {
+    pub strings: Vec<u8>,
+}
+
+impl Tuning {
+    /// E A D G B E.
+    pub fn standard() -> Self {
+        Tuning {
+            strings: vec![40, 45, 50, 55, 59, 64],
+        }
+    }
+
+    /// D A D G B E.
+    pub fn drop_d() -> Self {
+        Tuning {
+            strings: vec![38, 45, 50, 55, 59, 64],
+        }
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning::standard()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeKind {
+    /// Root on the 6th string: R x 7 3 5 x.
+    RootSixth,
+    /// Root on the 5th string: x R 5 7 3 x.
+    RootFifth,
+    /// Root on the 4th string: x x R 5 7 3.
+    RootFourth,
+    /// A drop 2 voicing on four adjacent strings.
+    Drop2,
+    /// A drop 3 voicing with a string skipped above the bass.
+    Drop3,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuitarShape {
+    pub chord: Chord,
+    pub kind: ShapeKind,
+    /// The fret played on each string, lowest string first. `None` is a
+    /// muted string and `Some(0)` an open one.
+    pub frets: Vec<Option<u8>>,
+}
+
+/// A shape chosen for a chord of the tune.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuitarChord {
+    /// Index into `Music.written_bars`.
+    pub bar: usize,
+    pub beat: u32,
+    pub shape: GuitarShape,
+}
+
+impl GuitarShape {
+    /// The sounding notes as MIDI notes, lowest first.
+    pub fn notes(&self, tuning: &Tuning) -> Vec<u8> {
+        self.frets
+            .iter()
+            .zip(&tuning.strings)
+            .filter_map(|(fret, open)| fret.map(|fret| open.saturating_add(fret)))
+            .collect()
+    }
+
+    /// The fret the diagram starts at: 1 for shapes near the nut, otherwise
+    /// the lowest fretted note.
+    pub fn base_fret(&self) -> u8 {
+        let fretted = self.frets.iter().flatten().filter(|&&f| f > 0);
+        match (fretted.clone().min(), fretted.max()) {
+            (Some(&low), Some(&high)) if high > 4 => low,
+            _ => 1,
+        }
+    }
+
+    // Where the hand sits on the neck.
+    fn position(&self) -> u8 {
+        self.frets
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|&f| f > 0)
+            .min()
+            .unwrap_or(0)
+    }
+
+    // Fret rows shown in a diagram.
+    fn rows(&self) -> usize {
+        let highest = self.frets.iter().flatten().max().copied().unwrap_or(0);
+        (usize::from(highest) + 1)
+            .saturating_sub(usize::from(self.base_fret()))
+            .max(4)
+    }
+
+    /// A chord box drawn in text, with strings running down and the lowest
+    /// string on the left.
+    ///
+    /// ```text
+    /// D-7
+    /// x x o
+    /// ===========
+    /// | | | | * *
+    /// | | | * | |
+    /// | | | | | |
+    /// | | | | | |
+    /// ```
+    pub fn to_ascii(&self) -> String {
+        let width = (self.frets.len() * 2).saturating_sub(1);
+        let base = self.base_fret();
+        let mut lines = vec![self.chord.to_string()];
+        let markers: Vec<&str> = self
+            .frets
+            .iter()
+            .map(|fret| match fret {
+                None => "x",
+                Some(0) => "o",
+                Some(_) => " ",
+            })
+            .collect();
+        lines.push(markers.join(" ").trim_end().to_string());
+        lines.push(if base == 1 { "=" } else { "-" }.repeat(width));
+        for row in 0..self.rows() {
+            let fret = usize::from(base) + row;
+            let strings: Vec<&str> = self
+                .frets
+                .iter()
+                .map(|f| {
+                    if f.map(usize::from) == Some(fret) {
+                        "*"
+                    } else {
+                        "|"
+                    }
+                })
+                .collect();
+            let mut line = strings.join(" ");
+            if row == 0 && base > 1 {
+                write!(line, "  {}fr", base).unwrap();
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    /// A chord box as a standalone SVG image.
+    pub fn to_svg(&self) -> String {
+        const STRING: f64 = 12.0;
+        const FRET: f64 = 14.0;
+        const LEFT: f64 = 14.0;
+        const TOP: f64 = 34.0;
+        let strings = self.frets.len();
+        let rows = self.rows();
+        let right = LEFT + STRING * strings.saturating_sub(1) as f64;
+        let bottom = TOP + FRET * rows as f64;
+        let base = self.base_fret();
+
+        let mut svg = String::new();
+        write!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="10">"#,
+            right + 28.0,
+            bottom + 6.0
+        )
+        .unwrap();
+        write!(
+            svg,
+            r#"<text x="{}" y="12" text-anchor="middle" font-size="12">{}</text>"#,
+            (LEFT + right) / 2.0,
+            escape(&self.chord.to_string())
+        )
+        .unwrap();
+        for i in 0..strings {
+            let x = LEFT + STRING * i as f64;
+            write!(
+                svg,
+                r#"<line x1="{x}" y1="{TOP}" x2="{x}" y2="{bottom}" stroke="black"/>"#
+            )
+            .unwrap();
+        }
+        for row in 0..=rows {
+            let y = TOP + FRET * row as f64;
+            let width = if row == 0 && base == 1 { 3 } else { 1 };
+            write!(
+                svg,
+                r#"<line x1="{LEFT}" y1="{y}" x2="{right}" y2="{y}" stroke="black" stroke-width="{width}"/>"#
+            )
+            .unwrap();
+        }
+        if base > 1 {
+            write!(
+                svg,
+                r#"<text x="{}" y="{}">{}fr</text>"#,
+                right + 4.0,
+                TOP + FRET * 0.7,
+                base
+            )
+            .unwrap();
+        }
+        for (i, fret) in self.frets.iter().enumerate() {
+            let x = LEFT + STRING * i as f64;
+            match fret {
+                None | Some(0) => {
+                    let marker = if fret.is_none() { "x" } else { "o" };
+                    write!(
+                        svg,
+                        r#"<text x="{x}" y="{}" text-anchor="middle">{marker}</text>"#,
+                        TOP - 4.0
+                    )
+                    .unwrap();
+                }
+                Some(fret) => {
+                    let y = TOP + FRET * ((fret - base) as f64 + 0.5);
+                    write!(svg, r#"<circle cx="{x}" cy="{y}" r="4.5"/>"#).unwrap();
+                }
+            }
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+/* Templates for each kind of shape: the string the shape starts on, counting
+ * from the lowest, and the chord tone on each string from there up. `None`
+ * skips a string. Tones are 0 root, 1 third, 2 fifth, 3 seventh. */
+fn templates(kind: ShapeKind) -> Vec<(usize, Vec<Option<usize>>)> {
+    let close = [0, 1, 2, 3];
+    let inversions = (0..4).map(move |i| [0, 1, 2, 3].map(|j| close[(i + j) % 4]));
+    match kind {
+        ShapeKind::RootSixth => vec![(0, vec![Some(0), None, Some(3), Some(1), Some(2)])],
+        ShapeKind::RootFifth => vec![(1, vec![Some(0), Some(2), Some(3), Some(1)])],
+        ShapeKind::RootFourth => vec![(2, vec![Some(0), Some(2), Some(3), Some(1)])],
+        ShapeKind::Drop2 => [1, 2]
+            .into_iter()
+            .flat_map(|string| {
+                inversions
+                    .clone()
+                    .map(move |[a, b, c, d]| (string, vec![Some(c), Some(a), Some(b), Some(d)]))
+            })
+            .collect(),
+        ShapeKind::Drop3 => [0, 1]
+            .into_iter()
+            .flat_map(|string| {
+                inversions.clone().map(move |[a, b, c, d]| {
+                    (string, vec![Some(b), None, Some(a), Some(c), Some(d)])
+                })
+            })
+            .collect(),
+    }
+}
+
+/// Playable shapes for `chord` on an instrument tuned to `tuning`, lowest on
+/// the neck first. Shapes whose notes wouldn't rise from string to string in
+/// this tuning are left out.
+pub fn guitar_shapes(chord: &Chord, tuning: &Tuning) -> Vec<GuitarShape> {
+    let (root, tones) = match (chord.root_pitch_class(), tones(chord)) {
+        (Some(root), Some(tones)) => (root, tones),
+        _ => return vec![],
+    };
+    let intervals = [0, tones.third, tones.fifth, tones.seventh];
+    let fret_for = |string: usize, tone: usize| {
+        (root + intervals[tone] + 12 * 12 - tuning.strings[string] % 12) % 12
+    };
+
+    let mut shapes: Vec<GuitarShape> = vec![];
+    for kind in [
+        ShapeKind::RootSixth,
+        ShapeKind::RootFifth,
+        ShapeKind::RootFourth,
+        ShapeKind::Drop2,
+        ShapeKind::Drop3,
+    ] {
+        for (first, template) in templates(kind) {
+            if first + template.len() > tuning.strings.len() {
+                continue;
+            }
+            let bass = fret_for(first, template[0].unwrap());
+            for bass in [bass, bass + 12] {
+                if bass > MAX_FRET {
+                    continue;
+                }
+                let mut frets = vec![None; tuning.strings.len()];
+                for (offset, tone) in template.iter().enumerate() {
+                    if let Some(tone) = tone {
+                        // The fret for this tone nearest the bass note.
+                        let low = fret_for(first + offset, *tone);
+                        let fret = [low, low + 12]
+                            .into_iter()
+                            .filter(|&f| f <= MAX_FRET)
+                            .min_by_key(|&f| f.abs_diff(bass))
+                            .unwrap();
+                        frets[first + offset] = Some(fret);
+                    }
+                }
+                let shape = GuitarShape {
+                    chord: chord.clone(),
+                    kind,
+                    frets,
+                };
+                if playable(&shape, tuning) && !shapes.iter().any(|s| s.frets == shape.frets) {
+                    shapes.push(shape);
+                }
+            }
+        }
+    }
+    shapes.sort_by_key(|shape| shape.position());
+    shapes
+}
+
+fn playable(shape: &GuitarShape, tuning: &Tuning) -> bool {
+    let fretted: Vec<u8> = shape
+        .frets
+        .iter()
+        .flatten()
+        .copied()
+        .filter(|&f| f > 0)
+        .collect();
+    let (low, high) = match (fretted.iter().min(), fretted.iter().max()) {
+        (Some(&low), Some(&high)) => (low, high),
+        _ => (0, 0),
+    };
+    // Open strings only go with shapes near the nut.
+    let open = shape.frets.contains(&Some(0));
+    let notes = shape.notes(tuning);
+    high - low <= MAX_SPAN && !(open && high > 4) && notes.windows(2).all(|w| w[0] < w[1])
+}
+
+impl Music {
+    /// A guitar shape for every chord, chosen so the voices and the hand move
+    /// as little as possible through the tune.
+    pub fn guitar_chords(&self, tuning: &Tuning) -> Vec<GuitarChord> {
+        let timeline = self.timeline();
+        let chords: Vec<(usize, Vec<GuitarShape>)> = timeline
+            .iter()
+            .enumerate()
+            .map(|(i, timed)| (i, guitar_shapes(&timed.chord, tuning)))
+            .filter(|(_, shapes)| !shapes.is_empty())
+            .collect();
+        if chords.is_empty() {
+            return vec![];
+        }
+        let cost = |from: &GuitarShape, to: &GuitarShape| {
+            movement(&from.notes(tuning), &to.notes(tuning))
+                + 2 * from.position().abs_diff(to.position()) as u32
+        };
+
+        // The same search as for piano voicings, starting low on the neck.
+        let mut total: Vec<u32> = chords[0].1.iter().map(|s| s.position() as u32).collect();
+        let mut back: Vec<Vec<usize>> = vec![vec![0; total.len()]];
+        for pair in chords.windows(2) {
+            let (previous, current) = (&pair[0].1, &pair[1].1);
+            let mut next_total = vec![];
+            let mut from = vec![];
+            for shape in current {
+                let (best, j) = previous
+                    .iter()
+                    .enumerate()
+                    .map(|(j, prev)| (total[j] + cost(prev, shape), j))
+                    .min()
+                    .unwrap();
+                next_total.push(best);
+                from.push(j);
+            }
+            total = next_total;
+            back.push(from);
+        }
+        let mut choice = (0..total.len()).min_by_key(|&k| total[k]).unwrap();
+        let mut choices = vec![0; chords.len()];
+        for i in (0..chords.len()).rev() {
+            choices[i] = choice;
+            choice = back[i][choice];
+        }
+
+        chords
+            .into_iter()
+            .zip(choices)
+            .map(|((i, mut shapes), choice)| GuitarChord {
+                bar: timeline[i].bar,
+                beat: timeline[i].beat,
+                shape: shapes.swap_remove(choice),
+            })
+            .collect()
+    }
+
+    /// The tune as text, one line of chord boxes per bar.
+    pub fn guitar_chart(&self, tuning: &Tuning) -> String {
+        let chords = self.guitar_chords(tuning);
+        let mut chart = String::new();
+        for (bar, _) in self.written_bars.iter().enumerate() {
+            let boxes: Vec<Vec<String>> = chords
+                .iter()
+                .filter(|c| c.bar == bar)
+                .map(|c| c.shape.to_ascii().lines().map(String::from).collect())
+                .collect();
+            if boxes.is_empty() {
+                continue;
+            }
+            writeln!(chart, "Bar {}", bar + 1).unwrap();
+            let height = boxes.iter().map(|b| b.len()).max().unwrap();
+            for line in 0..height {
+                let row: Vec<String> = boxes
+                    .iter()
+                    .map(|b| {
+                        let width = b.iter().map(|l| l.chars().count()).max().unwrap();
+                        let text = b.get(line).map_or("", |l| l.as_str());
+                        format!("{:width$}", text)
+                    })
+                    .collect();
+                writeln!(chart, "{}", row.join("   ").trim_end()).unwrap();
+            }
+            chart.push('\n');
+        }
+        chart
+    }
+}
@@ -0,0 +1,408 @@
+use std::{collections::HashSet, fmt::Write};
+
+use crate::{
+    annotation::Annotation,
+    drawing::{accidentals, chord_parts},
+    layout::{Barline, Layout, Row},
+    parse::{CellContent, Music},
+    svg::escape,
+    tokenize::Width,
+    transpose::Part,
+    types::Chord,
+    Collection, Song,
+};
+
+/// The stylesheet used by the HTML charts. Pages made with `to_html`
+/// include it; pages embedding `chart_html` output need to link it.
+pub const CHART_CSS: &str = r#".ireal-chart {
+  font-family: Helvetica, Arial, sans-serif;
+  font-size: clamp(11px, 2.4vw, 20px);
+  max-width: 50em;
+  margin: 0 auto;
+}
+.ireal-chart header { display: grid; grid-template-columns: 1fr auto; margin-bottom: 1.5em; }
+.ireal-chart h1 { grid-column: 1 / 3; text-align: center; margin: 0 0 0.3em; }
+.ireal-chart .part { grid-column: 1 / 3; margin: 0; font-size: 0.8em; }
+.ireal-chart .details { margin: 0; font-size: 0.8em; }
+.ireal-chart .composer { margin: 0; font-size: 0.8em; text-align: right; }
+.ireal-chart .row {
+  display: grid;
+  grid-template-columns: repeat(16, 1fr);
+  margin-top: calc(1.6em + var(--space, 0) * 1em);
+  min-height: 2.4em;
+}
+.ireal-chart .bar {
+  position: relative;
+  display: grid;
+  grid-template-columns: repeat(var(--cells), 1fr);
+  align-items: center;
+  padding: 0 0.2em;
+  border-left: 1px solid;
+}
+.ireal-chart .bar.start-double { border-left: 4px double; }
+.ireal-chart .bar.end-single { border-right: 1px solid; }
+.ireal-chart .bar.end-double { border-right: 4px double; }
+.ireal-chart .bar.end-final { border-right: 5px solid; }
+.ireal-chart .bar.start-repeat, .ireal-chart .bar.start-repeat-end { border-left: 0; }
+.ireal-chart .bar.end-repeat-end { border-right: 0; }
+.ireal-chart .repeat { font-size: 2em; line-height: 1; }
+.ireal-chart .repeat.end { position: absolute; right: -0.1em; }
+.ireal-chart .repeat.start { position: absolute; left: -0.1em; }
+.ireal-chart .cell { white-space: nowrap; position: relative; padding-left: 0.3em; }
+.ireal-chart .chord sup { font-size: 0.65em; }
+.ireal-chart .chord.narrow { font-size: 0.75em; }
+.ireal-chart .alternate { position: absolute; bottom: 1.6em; left: 0.3em; font-size: 0.6em; }
+.ireal-chart .simile { grid-column: 1 / -1; text-align: center; font-size: 1.6em; }
+.ireal-chart .marks { position: absolute; bottom: 100%; left: -0.3em; white-space: nowrap; }
+.ireal-chart .section {
+  display: inline-block;
+  border: 1px solid;
+  padding: 0 0.2em;
+  font-weight: bold;
+}
+.ireal-chart .time {
+  display: inline-flex;
+  flex-direction: column;
+  vertical-align: middle;
+  font-size: 0.55em;
+  font-weight: bold;
+  line-height: 1;
+  text-align: center;
+}
+.ireal-chart .ending + .marks { left: 1.6em; }
+.ireal-chart .glyph { font-size: 1.3em; }
+.ireal-chart .end { font-size: 0.7em; font-weight: bold; margin-left: 0.3em; }
+.ireal-chart .ending {
+  position: absolute;
+  bottom: 100%;
+  left: 0;
+  width: var(--span);
+  border-left: 1px solid;
+  border-top: 1px solid;
+  padding-left: 0.2em;
+  font-size: 0.7em;
+  box-sizing: border-box;
+}
+.ireal-chart .comment {
+  position: absolute;
+  top: calc(100% + var(--line) * 1.2em);
+  left: 0.3em;
+  font-size: 0.65em;
+  font-style: italic;
+  white-space: nowrap;
+}
+.ireal-chart .comment.direction { font-style: normal; font-weight: bold; }
+.ireal-index { font-family: Helvetica, Arial, sans-serif; max-width: 40em; margin: 0 auto; }
+.ireal-index li { display: flex; justify-content: space-between; gap: 1em; }
+.ireal-index .composer { color: #555; }
+"#;
+
+const SEGNO: &str = "\u{1d10b}";
+const CODA: &str = "\u{1d10c}";
+const FERMATA: &str = "\u{1d110}";
+const REPEAT_START: &str = "\u{1d106}";
+const REPEAT_END: &str = "\u{1d107}";
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        CHART_CSS,
+        body
+    )
+}
+
+fn chord_html(chord: &Chord, width: &Width) -> String {
+    let (root, suffix, bass) = chord_parts(chord);
+    let class = match width {
+        Width::Wide => "chord",
+        Width::Narrow => "chord narrow",
+    };
+    let mut html = format!(r#"<span class="{}">{}"#, class, escape(&root));
+    if !suffix.is_empty() {
+        write!(html, "<sup>{}</sup>", escape(&suffix)).unwrap();
+    }
+    write!(html, "{}</span>", escape(&bass)).unwrap();
+    html
+}
+
+fn barline_class(barline: Barline) -> &'static str {
+    match barline {
+        Barline::Single => "single",
+        Barline::Double => "double",
+        Barline::Final => "final",
+        Barline::RepeatStart => "repeat",
+        Barline::RepeatEnd | Barline::RepeatBoth => "repeat-end",
+    }
+}
+
+fn bar_html(html: &mut String, row: &Row, index: usize) {
+    let bar = &row.bars[index];
+    let shows_end = row.shows_end(index);
+    let mut class = format!("bar start-{}", barline_class(bar.start));
+    if shows_end {
+        write!(class, " end-{}", barline_class(bar.end)).unwrap();
+    }
+    writeln!(
+        html,
+        r#"<div class="{}" style="grid-column: {} / span {}; --cells: {}">"#,
+        class,
+        bar.first_cell + 1,
+        bar.cells.len(),
+        bar.cells.len()
+    )
+    .unwrap();
+
+    match bar.start {
+        Barline::RepeatStart => writeln!(
+            html,
+            r#"<span class="repeat start">{}</span>"#,
+            REPEAT_START
+        )
+        .unwrap(),
+        Barline::RepeatEnd => {
+            writeln!(html, r#"<span class="repeat start">{}</span>"#, REPEAT_END).unwrap()
+        }
+        Barline::RepeatBoth => writeln!(
+            html,
+            r#"<span class="repeat start">{}{}</span>"#,
+            REPEAT_END, REPEAT_START
+        )
+        .unwrap(),
+        _ => {}
+    }
+    if let Some(n) = bar.ending {
+        // Size the bracket as a share of this bar's width.
+        let cells: usize = row.bars[index..]
+            .iter()
+            .take(bar.ending_bars)
+            .map(|b| b.cells.len())
+            .sum();
+        writeln!(
+            html,
+            r#"<span class="ending" style="--span: {}%">{}.</span>"#,
+            cells * 100 / bar.cells.len().max(1),
+            n
+        )
+        .unwrap();
+    }
+    let mut marks = String::new();
+    if let Some(section) = &bar.section {
+        write!(marks, r#"<span class="section">{}</span>"#, escape(section)).unwrap();
+    }
+    if let Some(ts) = &bar.time_signature {
+        write!(
+            marks,
+            r#"<span class="time"><span>{}</span><span>{}</span></span>"#,
+            ts.top, ts.bottom
+        )
+        .unwrap();
+    }
+    for (shown, glyph) in [(bar.segno, SEGNO), (bar.coda, CODA), (bar.fermata, FERMATA)] {
+        if shown {
+            write!(marks, r#"<span class="glyph">{}</span>"#, glyph).unwrap();
+        }
+    }
+    if bar.ending_measure {
+        marks.push_str(r#"<span class="end">END</span>"#);
+    }
+    if !marks.is_empty() {
+        writeln!(html, r#"<span class="marks">{}</span>"#, marks).unwrap();
+    }
+
+    if bar.simile > 0 {
+        let glyph = if bar.simile == 2 {
+            "\u{1d10e}"
+        } else {
+            "\u{1d10d}"
+        };
+        writeln!(html, r#"<span class="simile">{}</span>"#, glyph).unwrap();
+    } else {
+        for cell in &bar.cells {
+            html.push_str(r#"<span class="cell">"#);
+            match &cell.content {
+                Some(CellContent::Chord(chord, width)) => html.push_str(&chord_html(chord, width)),
+                Some(CellContent::Slash) => html.push('/'),
+                // Repeat signs are drawn across the whole bar.
+                _ => {}
+            }
+            if let Some(alternate) = &cell.alternate {
+                write!(
+                    html,
+                    r#"<span class="alternate">{}</span>"#,
+                    chord_html(alternate, &Width::Narrow)
+                )
+                .unwrap();
+            }
+            html.push_str("</span>\n");
+        }
+    }
+    for (i, comment) in bar.comments.iter().enumerate() {
+        let class = match comment {
+            Annotation::Direction { .. } | Annotation::Fine { .. } => "comment direction",
+            _ => "comment",
+        };
+        writeln!(
+            html,
+            r#"<span class="{}" style="--line: {}">{}</span>"#,
+            class,
+            i,
+            escape(&comment.to_string())
+        )
+        .unwrap();
+    }
+    if shows_end && matches!(bar.end, Barline::RepeatEnd) {
+        writeln!(html, r#"<span class="repeat end">{}</span>"#, REPEAT_END).unwrap();
+    }
+    html.push_str("</div>\n");
+}
+
+fn layout_html(html: &mut String, layout: &Layout) {
+    for row in &layout.rows {
+        if row.space > 0 {
+            writeln!(html, r#"<div class="row" style="--space: {}">"#, row.space).unwrap();
+        } else {
+            html.push_str("<div class=\"row\">\n");
+        }
+        for index in 0..row.bars.len() {
+            bar_html(html, row, index);
+        }
+        html.push_str("</div>\n");
+    }
+}
+
+impl Music {
+    /// The chart as an HTML fragment, styled by `CHART_CSS`.
+    pub fn chart_html(&self) -> String {
+        let mut html = String::from("<div class=\"ireal-chart\">\n");
+        layout_html(&mut html, &self.layout());
+        html.push_str("</div>\n");
+        html
+    }
+}
+
+impl Song {
+    fn header_html(&self, label: Option<&str>) -> String {
+        let mut html = String::from("<header>\n");
+        writeln!(html, "<h1>{}</h1>", escape(&self.title)).unwrap();
+        if let Some(label) = label {
+            writeln!(html, r#"<p class="part">{}</p>"#, escape(label)).unwrap();
+        }
+        let mut details = format!("({})", self.style);
+        if !self.key.is_empty() {
+            details.push_str(&format!(" Key: {}", accidentals(&self.key)));
+        }
+        if self.bpm > 0 {
+            details.push_str(&format!(" {} bpm", self.bpm));
+        }
+        writeln!(html, r#"<p class="details">{}</p>"#, escape(&details)).unwrap();
+        writeln!(
+            html,
+            r#"<p class="composer">{}</p>"#,
+            escape(&self.composer)
+        )
+        .unwrap();
+        html.push_str("</header>\n");
+        html
+    }
+
+    fn chart_with_header(&self, label: Option<&str>) -> String {
+        let mut html = String::from("<div class=\"ireal-chart\">\n");
+        html.push_str(&self.header_html(label));
+        layout_html(&mut html, &self.music.layout());
+        html.push_str("</div>\n");
+        html
+    }
+
+    /// The chart with its header as an HTML fragment, styled by `CHART_CSS`.
+    pub fn chart_html(&self) -> String {
+        self.chart_with_header(None)
+    }
+
+    /// The chart as a standalone HTML page.
+    pub fn to_html(&self) -> String {
+        page(&self.title, &self.chart_html())
+    }
+}
+
+impl Part {
+    /// The part's chart as an HTML fragment, styled by `CHART_CSS`.
+    pub fn chart_html(&self) -> String {
+        self.song
+            .chart_with_header(self.instrument.label().as_deref())
+    }
+
+    /// The part's chart as a standalone HTML page.
+    pub fn to_html(&self) -> String {
+        page(&self.song.title, &self.chart_html())
+    }
+}
+
+/// Make a title safe to use as a file name.
+pub fn file_name(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = name.trim_matches('_');
+    if name.is_empty() {
+        "untitled".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+impl Collection {
+    /// File names for the songs, made from their titles and ending in
+    /// `extension`. Songs with the same title are numbered.
+    pub fn file_names(&self, extension: &str) -> Vec<String> {
+        let mut used = HashSet::new();
+        self.songs
+            .iter()
+            .map(|song| {
+                let base = file_name(&song.title);
+                let mut name = format!("{}.{}", base, extension);
+                let mut n = 2;
+                while !used.insert(name.clone()) {
+                    name = format!("{}_{}.{}", base, n, extension);
+                    n += 1;
+                }
+                name
+            })
+            .collect()
+    }
+
+    /// File names for the songs' pages.
+    pub fn html_file_names(&self) -> Vec<String> {
+        self.file_names("html")
+    }
+
+    /// An index page linking to each song's page, in title order.
+    pub fn to_html_index(&self) -> String {
+        let mut entries: Vec<(&Song, String)> =
+            self.songs.iter().zip(self.html_file_names()).collect();
+        entries.sort_by_key(|(song, _)| song.title.to_lowercase());
+        let mut body = String::from("<div class=\"ireal-index\">\n");
+        writeln!(body, "<h1>{}</h1>\n<ul>", escape(&self.title)).unwrap();
+        for (song, name) in entries {
+            writeln!(
+                body,
+                r#"<li><a href="{}">{}</a> <span class="composer">{}</span></li>"#,
+                escape(&name),
+                escape(&song.title),
+                escape(&song.composer)
+            )
+            .unwrap();
+        }
+        body.push_str("</ul>\n</div>\n");
+        page(&self.title, &body)
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    analysis::{analyze_chord, HarmonicFunction, Quality},
+    parse::Music,
+    timeline::TimedChord,
+    types::{Chord, Key},
+};
+
+const MAJOR_SCALE: [u8; 7] = [0, 2, 4, 5, 7, 9, 11];
+// Natural and harmonic minor together.
+const MINOR_SCALE: [u8; 8] = [0, 2, 3, 5, 7, 8, 10, 11];
+
+/* How much staying in the same key is preferred over modulating, in beats
+ * of perfectly fitting harmony. Two bars of 4/4 in the new key are needed
+ * before a modulation is worth it. */
+const MODULATION_COST: f64 = 8.0;
+
+/// A stretch of music that stays in one key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyRegion {
+    pub key: Key,
+    /// Where the region starts, as an index into `Music.written_bars` and a
+    /// beat within that bar.
+    pub bar: usize,
+    pub beat: u32,
+    /// Where the last chord in the region starts.
+    pub end_bar: usize,
+    pub end_beat: u32,
+    /// Length of the region in beats.
+    pub beats: u32,
+    /// From 0 to 1, how much better `key` explains the chords than the next
+    /// best key.
+    pub confidence: f64,
+}
+
+fn all_keys() -> Vec<Key> {
+    (0..12)
+        .flat_map(|pc| {
+            [
+                Key::from_pitch_class(pc, false),
+                Key::from_pitch_class(pc, true),
+            ]
+        })
+        .collect()
+}
+
+/// How well `chord` fits `key`, per beat. Roughly -1 (nothing in common) to
+/// 2 (the tonic chord).
+fn fit(chord: &Chord, key: &Key) -> f64 {
+    let (tonic, root) = match (key.tonic.pitch_class(), chord.root_pitch_class()) {
+        (Some(tonic), Some(root)) => (tonic, root),
+        _ => return 0.0,
+    };
+    let scale: &[u8] = if key.minor {
+        &MINOR_SCALE
+    } else {
+        &MAJOR_SCALE
+    };
+    let in_scale = |pc: &u8| scale.contains(&((pc + 12 - tonic) % 12));
+    // The root counts twice.
+    let pitch_classes = chord.pitch_classes();
+    let total = pitch_classes.len() + 1;
+    let matching =
+        pitch_classes.iter().filter(|pc| in_scale(pc)).count() + usize::from(in_scale(&root));
+    let mut score = 2.0 * matching as f64 / total as f64 - 1.0;
+
+    if let Some((_, _, function)) = analyze_chord(chord, key) {
+        let interval = (root + 12 - tonic) % 12;
+        match function {
+            HarmonicFunction::Diatonic => {
+                score += 0.5;
+                if interval == 0 {
+                    score += 1.0;
+                } else if interval == 7 && chord.quality() == Some(Quality::Dominant) {
+                    score += 0.5;
+                }
+            }
+            // Secondary dominants and borrowed chords are at home in the
+            // key even though they bring in other notes.
+            HarmonicFunction::SecondaryDominant(_) => score = score.max(0.3),
+            HarmonicFunction::Borrowed => score = score.max(0.0),
+            HarmonicFunction::Chromatic => {}
+        }
+    }
+    score
+}
+
+fn region(key: &Key, chords: &[TimedChord], scores: &[Vec<f64>], keys: &[Key]) -> KeyRegion {
+    let k = keys.iter().position(|candidate| candidate == key).unwrap();
+    let total = |k: usize| -> f64 { scores.iter().map(|s| s[k]).sum() };
+    let chosen = total(k);
+    let runner_up = (0..keys.len())
+        .filter(|&other| other != k)
+        .map(total)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let beats: u32 = chords.iter().map(|c| c.beats).sum();
+    let margin = (chosen - runner_up) / beats.max(1) as f64;
+    let first = chords.first().unwrap();
+    let last = chords.last().unwrap();
+    KeyRegion {
+        key: key.clone(),
+        bar: first.bar,
+        beat: first.beat,
+        end_bar: last.bar,
+        end_beat: last.beat,
+        beats,
+        confidence: (margin / (margin + 0.5)).clamp(0.0, 1.0),
+    }
+}
+
+impl Music {
+    /// Split the music into regions in different keys, based only on the
+    /// chords. The key in the song's metadata isn't used.
+    pub fn key_regions(&self) -> Vec<KeyRegion> {
+        let chords: Vec<TimedChord> = self
+            .timeline()
+            .into_iter()
+            .filter(|timed| timed.chord.root_pitch_class().is_some())
+            .collect();
+        if chords.is_empty() {
+            return vec![];
+        }
+        let keys = all_keys();
+        let scores: Vec<Vec<f64>> = chords
+            .iter()
+            .map(|timed| {
+                keys.iter()
+                    .map(|key| fit(&timed.chord, key) * timed.beats as f64)
+                    .collect()
+            })
+            .collect();
+
+        // Viterbi: the best total score for each key at each chord, and the
+        // key of the chord before it on that best path.
+        let mut best: Vec<f64> = scores[0].clone();
+        let mut back: Vec<Vec<usize>> = vec![vec![0; keys.len()]];
+        for score in &scores[1..] {
+            // Modulating means coming from the best key so far.
+            let best_from = (0..keys.len())
+                .max_by(|&a, &b| best[a].total_cmp(&best[b]))
+                .unwrap();
+            let modulate = best[best_from] - MODULATION_COST;
+            let mut next = vec![0.0; keys.len()];
+            let mut from = vec![0; keys.len()];
+            for k in 0..keys.len() {
+                if best[k] >= modulate {
+                    next[k] = best[k] + score[k];
+                    from[k] = k;
+                } else {
+                    next[k] = modulate + score[k];
+                    from[k] = best_from;
+                }
+            }
+            best = next;
+            back.push(from);
+        }
+
+        let mut k = (0..keys.len())
+            .max_by(|&a, &b| best[a].total_cmp(&best[b]))
+            .unwrap();
+        let mut path = vec![k; chords.len()];
+        for i in (1..chords.len()).rev() {
+            k = back[i][k];
+            path[i - 1] = k;
+        }
+
+        let mut regions = vec![];
+        let mut start = 0;
+        for i in 1..=chords.len() {
+            if i == chords.len() || path[i] != path[start] {
+                regions.push(region(
+                    &keys[path[start]],
+                    &chords[start..i],
+                    &scores[start..i],
+                    &keys,
+                ));
+                start = i;
+            }
+        }
+        regions
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    annotation::Annotation,
+    parse::{Cell, Music, WrittenBar, WrittenElement},
+    types::TimeSignature,
+};
+
+/// iReal charts are drawn on a grid of 16 cells per row.
+pub const CELLS_PER_ROW: usize = 16;
+/// Cells given to a measure, so four measures make a row.
+pub const CELLS_PER_BAR: usize = 4;
+
+/// The barline drawn at the start or end of a bar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Barline {
+    Single,
+    Double,
+    Final,
+    RepeatStart,
+    RepeatEnd,
+    /// The end of one repeat and the start of the next.
+    RepeatBoth,
+}
+
+/// A written bar placed on the grid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BarLayout {
+    /// Index into `Music.written_bars`.
+    pub bar: usize,
+    /// Column of the first cell in the row, from 0 to 15.
+    pub first_cell: usize,
+    pub cells: Vec<Cell>,
+    /// The barline before the bar. Where two bars meet this combines the
+    /// end of the first with the start of the second.
+    pub start: Barline,
+    /// The barline after the bar. Only drawn for the last bar of a row, or
+    /// before a gap.
+    pub end: Barline,
+    pub section: Option<String>,
+    /// Set where the time signature changes, and on the first bar.
+    pub time_signature: Option<TimeSignature>,
+    pub ending: Option<u32>,
+    /// How many bars, starting with this one, the ending bracket covers.
+    pub ending_bars: usize,
+    /// Measures repeated by a repeat sign filling the bar: 0, 1 or 2.
+    pub simile: u32,
+    pub segno: bool,
+    pub coda: bool,
+    pub fermata: bool,
+    /// The tune ends after this bar on the last time through.
+    pub ending_measure: bool,
+    pub comments: Vec<Annotation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    /// Extra space above the row, in steps of iReal's vertical spacer.
+    pub space: u32,
+    pub bars: Vec<BarLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub rows: Vec<Row>,
+}
+
+impl Row {
+    /// Whether the end barline of the bar at `index` is drawn: it is for the
+    /// last bar, and for bars with a gap after them.
+    pub(crate) fn shows_end(&self, index: usize) -> bool {
+        match self.bars.get(index + 1) {
+            Some(next) => {
+                let bar = &self.bars[index];
+                next.first_cell > bar.first_cell + bar.cells.len()
+            }
+            None => true,
+        }
+    }
+}
+
+fn start_barline(bar: &WrittenBar) -> Barline {
+    if bar.repeat_start {
+        Barline::RepeatStart
+    } else if bar.double_start {
+        Barline::Double
+    } else {
+        Barline::Single
+    }
+}
+
+fn end_barline(bar: &WrittenBar) -> Barline {
+    if bar.repeat_end {
+        Barline::RepeatEnd
+    } else if bar.final_bar {
+        Barline::Final
+    } else if bar.double_end {
+        Barline::Double
+    } else {
+        Barline::Single
+    }
+}
+
+fn merge(end: Barline, start: Barline) -> Barline {
+    match (end, start) {
+        (Barline::RepeatEnd, Barline::RepeatStart) => Barline::RepeatBoth,
+        (_, Barline::Single) => end,
+        _ => start,
+    }
+}
+
+pub(crate) fn lay_out_bar(index: usize, bar: &WrittenBar) -> BarLayout {
+    let mut layout = BarLayout {
+        bar: index,
+        first_cell: 0,
+        cells: vec![],
+        start: start_barline(bar),
+        end: end_barline(bar),
+        section: None,
+        time_signature: None,
+        ending: None,
+        ending_bars: 0,
+        simile: 0,
+        segno: false,
+        coda: false,
+        fermata: false,
+        ending_measure: false,
+        comments: vec![],
+    };
+    for element in &bar.elements {
+        match element {
+            WrittenElement::SectionMarker(s) => layout.section = Some(s.clone()),
+            WrittenElement::TimeSignature(ts) => layout.time_signature = Some(ts.clone()),
+            WrittenElement::NumberedEnding(n) => layout.ending = Some(*n),
+            WrittenElement::RepeatMeasure => layout.simile = 1,
+            WrittenElement::RepeatTwoMeasures => layout.simile = 2,
+            WrittenElement::Coda => layout.coda = true,
+            WrittenElement::Segno => layout.segno = true,
+            WrittenElement::Fermata => layout.fermata = true,
+            WrittenElement::EndingMeasure => layout.ending_measure = true,
+            WrittenElement::Comment(annotation, _) => layout.comments.push(annotation.clone()),
+            WrittenElement::Chord(..)
+            | WrittenElement::AlternateChord(_)
+            | WrittenElement::PauseSlash => {}
+        }
+    }
+    // Bars made without any cells get the usual four.
+    layout.cells = if bar.cells.is_empty() {
+        vec![Cell::default(); CELLS_PER_BAR]
+    } else {
+        bar.cells.clone()
+    };
+    layout
+}
+
+impl Music {
+    /// Place the bars on iReal's grid of 16 cell rows, in the cells they
+    /// were written in. A bar that would run off the end of a row starts
+    /// the next one.
+    pub fn layout(&self) -> Layout {
+        let mut rows: Vec<Row> = vec![];
+        let mut current: Vec<BarLayout> = vec![];
+        let mut used = 0;
+        let mut space = 0;
+        let mut time_signature: Option<TimeSignature> = None;
+
+        for (index, bar) in self.written_bars.iter().enumerate() {
+            let mut layout = lay_out_bar(index, bar);
+            // Only show time signatures that change.
+            if layout.time_signature.is_none() && index == 0 {
+                layout.time_signature = Some(TimeSignature { top: 4, bottom: 4 });
+            }
+            if layout.time_signature.is_some() && layout.time_signature == time_signature {
+                layout.time_signature = None;
+            } else if layout.time_signature.is_some() {
+                time_signature = layout.time_signature.clone();
+            }
+
+            let mut position = used + bar.gap;
+            if position >= CELLS_PER_ROW || position + layout.cells.len() > CELLS_PER_ROW {
+                if !current.is_empty() {
+                    rows.push(Row {
+                        space,
+                        bars: current,
+                    });
+                    current = vec![];
+                    space = 0;
+                }
+                position %= CELLS_PER_ROW;
+                if position + layout.cells.len() > CELLS_PER_ROW {
+                    position = 0;
+                }
+            }
+            space += bar.vertical_space;
+            layout.first_cell = position;
+            if let Some(previous) = current.last() {
+                if bar.gap == 0 {
+                    layout.start = merge(previous.end, layout.start);
+                }
+            }
+            used = position + layout.cells.len();
+            current.push(layout);
+        }
+        if !current.is_empty() {
+            rows.push(Row {
+                space,
+                bars: current,
+            });
+        }
+
+        // Ending brackets run until the repeat sign or the next ending.
+        for row in &mut rows {
+            for i in 0..row.bars.len() {
+                if row.bars[i].ending.is_none() {
+                    continue;
+                }
+                let mut span = 1;
+                while i + span < row.bars.len() {
+                    let (last, next) = (&row.bars[i + span - 1], &row.bars[i + span]);
+                    if last.end != Barline::Single
+                        || next.ending.is_some()
+                        || next.section.is_some()
+                        || next.start != Barline::Single
+                    {
+                        break;
+                    }
+                    span += 1;
+                }
+                row.bars[i].ending_bars = span;
+            }
+        }
+        Layout { rows }
+    }
+}
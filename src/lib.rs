@@ -1,7 +1,53 @@
+mod analysis;
+mod annotation;
+mod builder;
+mod drawing;
+mod form;
+mod guitar;
+mod html;
+mod key_regions;
+mod layout;
+mod notation;
 mod parse;
+mod patterns;
+mod pdf;
+mod scales;
+mod stream;
+mod svg;
+mod syntax;
+mod terminal;
+mod timeline;
 mod tokenize;
+mod transpose;
 mod types;
-use parse::Music;
+mod voicings;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::{borrow::Cow, fmt};
+
+pub use analysis::{analyze_chord, ChordAnalysis, HarmonicFunction, Quality};
+pub use annotation::{Annotation, Jump, Position, Until};
+pub use builder::{MusicBuilder, SongBuilder};
+pub use drawing::{Anchor, Drawing, Shape, PAGE_WIDTH};
+pub use form::{Form, FormKind, Section};
+pub use guitar::{guitar_shapes, GuitarChord, GuitarShape, ShapeKind, Tuning};
+pub use html::{file_name, CHART_CSS};
+pub use key_regions::KeyRegion;
+pub use layout::{BarLayout, Barline, Layout, Row, CELLS_PER_BAR, CELLS_PER_ROW};
+pub use notation::{ChordStyle, StyledChord};
+pub use parse::{Cell, CellContent, Music, ParseOptions, WrittenBar, WrittenElement};
+pub use patterns::{Pattern, PatternKind};
+pub use pdf::{PdfOptions, TocOrder};
+pub use scales::{scales_for_chord, ChordScales, Scale, ScaleSuggestion};
+pub use stream::SongIter;
+pub use syntax::{SyntaxNode, SyntaxTree};
+pub use terminal::TerminalOptions;
+pub use timeline::TimedChord;
+pub use tokenize::{tokenize, tokenize_lenient, MusicError, Spanned, Token, Width};
+pub use transpose::{Instrument, Part};
+pub use types::{AlteredNotes, Chord, Flavor, Key, Note, Number, TimeSignature};
+pub use voicings::{Voicing, VoicingOptions, VoicingStyle};
 
 const MUSIC_PREFIX: &str = "1r34LbKcu7";
 
@@ -9,50 +55,66 @@ const MUSIC_PREFIX: &str = "1r34LbKcu7";
  * Format reference: https://github.com/pianosnake/ireal-reader
  */
 
-fn unscramble(mut text: &str) -> String {
+fn unscramble(text: &str) -> String {
+    // Characters are swapped, not bytes, but most charts are plain ASCII,
+    // where the two are the same.
+    if text.is_ascii() {
+        let mut bytes = text.as_bytes().to_vec();
+        unscramble_units(&mut bytes);
+        String::from_utf8(bytes).unwrap()
+    } else {
+        let mut chars: Vec<char> = text.chars().collect();
+        unscramble_units(&mut chars);
+        chars.into_iter().collect()
+    }
+}
+
+fn unscramble_units<T>(mut units: &mut [T]) {
     /* Directly translated from
      * https://github.com/pianosnake/ireal-reader/blob/ce643f069732ab93b1dcbd621b6c0edfe9ab8a8b/unscramble.js#L5 */
-    let mut result = String::new();
-
-    while text.len() > 50 {
-        let (part, remainder) = text.split_at(50);
-        text = remainder;
-
-        if text.len() < 2 {
-            result.push_str(part);
-        } else {
-            result.push_str(&obfusc50(part));
+    while units.len() > 50 {
+        let (part, remainder) = units.split_at_mut(50);
+        if remainder.len() >= 2 {
+            obfusc50(part);
         }
+        units = remainder;
     }
+}
 
-    result.push_str(text);
-    result
+/* The obfuscation only swaps characters around, so scrambling is the same
+ * operation as unscrambling. */
+fn scramble(text: &str) -> String {
+    unscramble(text)
 }
 
-fn obfusc50(text: &str) -> String {
+fn obfusc50<T>(units: &mut [T]) {
     /* Directly translated from
      * https://github.com/pianosnake/ireal-reader/blob/ce643f069732ab93b1dcbd621b6c0edfe9ab8a8b/unscramble.js#L21 */
 
     // The first 5 characters are switched with the last 5.
-    let mut chars: Vec<char> = text.chars().collect();
-    let last = chars.len() - 1;
+    let last = units.len() - 1;
     for i in 0..5 {
-        chars.swap(i, last - i);
+        units.swap(i, last - i);
     }
     // Characters 10-24 are also switched.
     for i in 10..24 {
-        chars.swap(i, last - i);
+        units.swap(i, last - i);
     }
-
-    chars.into_iter().collect::<String>()
 }
 
-fn decode_music(text: &str) -> Result<Music, String> {
+fn decode_music(
+    text: &str,
+    options: &ParseOptions,
+) -> Result<(Music, Vec<MusicError>), MusicError> {
     if !text.starts_with(MUSIC_PREFIX) {
-        return Err(format!("Music doesn't start with {}", MUSIC_PREFIX));
+        let message = format!("Music doesn't start with {}", MUSIC_PREFIX);
+        return Err(MusicError::new(&message, text, 0));
     }
-    let unscrambled = unscramble(&text[MUSIC_PREFIX.len()..]);
-    parse::parse_music(unscrambled.as_str())
+    parse::read_music(unscramble(&text[MUSIC_PREFIX.len()..]), options)
+}
+
+fn encode_music(music: &Music) -> String {
+    format!("{}{}", MUSIC_PREFIX, scramble(&music.raw))
 }
 
 fn hex_digit_value(ch: char) -> Result<u32, String> {
@@ -83,7 +145,34 @@ fn hex_digit_value(ch: char) -> Result<u32, String> {
     }
 }
 
-fn unescape_percent(text: &str) -> Result<String, String> {
+fn unescape_percent(text: &str) -> Result<Cow<'_, str>, String> {
+    if !text.contains('%') {
+        return Ok(Cow::Borrowed(text));
+    }
+    // Escaped bytes are UTF-8, so collect bytes and decode at the end.
+    let mut result: Vec<u8> = Vec::with_capacity(text.len());
+    unescape_bytes(text.as_bytes(), &mut result)?;
+    Ok(Cow::Owned(decode_bytes(result)))
+}
+
+/* Unescaped text is UTF-8, but some older charts escape Latin-1, so bytes
+ * that aren't UTF-8 are read as Latin-1 rather than failing every song. */
+fn decode_bytes(bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => {
+            let mut text = String::new();
+            for chunk in e.as_bytes().utf8_chunks() {
+                text.push_str(chunk.valid());
+                text.extend(chunk.invalid().iter().map(|&b| b as char));
+            }
+            text
+        }
+    }
+}
+
+/* An escape cut off at the end of `bytes` is dropped. */
+fn unescape_bytes(bytes: &[u8], result: &mut Vec<u8>) -> Result<(), String> {
     enum UnescapeState {
         Plain,
         Percent,
@@ -91,26 +180,37 @@ fn unescape_percent(text: &str) -> Result<String, String> {
     }
 
     let mut state = UnescapeState::Plain;
-    let mut result = String::new();
     let mut num = 0;
-    for c in text.chars() {
+    for &byte in bytes {
         match state {
-            UnescapeState::Plain => match c {
-                '%' => state = UnescapeState::Percent,
-                _ => result.push(c),
+            UnescapeState::Plain => match byte {
+                b'%' => state = UnescapeState::Percent,
+                _ => result.push(byte),
             },
             UnescapeState::Percent => {
-                num = 16 * hex_digit_value(c)?;
+                num = 16 * hex_digit_value(byte as char)?;
                 state = UnescapeState::One
             }
             UnescapeState::One => {
-                num += hex_digit_value(c)?;
-                result.push(char::from_u32(num).unwrap());
+                num += hex_digit_value(byte as char)?;
+                result.push(num as u8);
                 state = UnescapeState::Plain
             }
         }
     }
-    Ok(result)
+    Ok(())
+}
+
+fn escape_percent(text: &str) -> String {
+    let mut result = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'=' {
+            result.push(byte as char);
+        } else {
+            result.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    result
 }
 
 #[derive(Debug, PartialEq)]
@@ -119,6 +219,20 @@ pub struct Collection {
     pub songs: Vec<Song>,
 }
 
+impl Collection {
+    /// Encode the collection as an irealb:// URL, which `parse_url` reads
+    /// back.
+    pub fn to_url(&self) -> String {
+        let mut text = String::new();
+        for song in &self.songs {
+            text.push_str(&song.to_text());
+            text.push_str("===");
+        }
+        text.push_str(&self.title);
+        format!("irealb://{}", escape_percent(&text))
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Eq)]
 pub struct Song {
     pub title: String,
@@ -130,26 +244,85 @@ pub struct Song {
     pub comp_style: String,
     pub bpm: u32,
     pub repeats: String,
+    /// Music that was skipped when the song was read leniently.
+    pub warnings: Vec<MusicError>,
+}
+
+/// A song in a collection that couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongError {
+    /// Where the song is in the collection, counting from 0.
+    pub index: usize,
+    pub title: Option<String>,
+    pub message: String,
+    /// Where the music went wrong, when it was the music that couldn't be
+    /// read.
+    pub music: Option<Box<MusicError>>,
+}
+
+impl fmt::Display for SongError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Song {}", self.index + 1)?;
+        if let Some(title) = &self.title {
+            write!(f, " {:?}", title)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl From<SongError> for String {
+    fn from(error: SongError) -> String {
+        error.to_string()
+    }
 }
 
 impl Song {
-    fn from_text(text: &str) -> Self {
+    fn from_text(index: usize, text: &str, options: &ParseOptions) -> Result<Self, SongError> {
         let parts: Vec<&str> = text.split("=").collect();
-        println!();
-        println!("Title: {}", parts[0]);
-        let song = Song {
-            title: parts[0].to_string(),
+        let title = parts[0];
+        let error = |message: String, music: Option<Box<MusicError>>| SongError {
+            index,
+            title: Some(title.to_string()).filter(|t| !t.is_empty()),
+            message,
+            music,
+        };
+        if parts.len() < 10 {
+            let message = format!("{} fields, expected 10", parts.len());
+            return Err(error(message, None));
+        }
+        let (music, warnings) = decode_music(parts[6], options)
+            .map_err(|e| error(e.to_string(), Some(Box::new(e.with_title(title)))))?;
+        let bpm = parts[8]
+            .parse()
+            .map_err(|_| error(format!("Invalid tempo {:?}", parts[8]), None))?;
+        Ok(Song {
+            title: title.to_string(),
             composer: parts[1].to_string(),
             style: parts[3].to_string(),
             key: parts[4].to_string(),
             transpose: parts[5].to_string(),
-            music: decode_music(parts[6]).unwrap(),
+            music,
             comp_style: parts[7].to_string(),
-            bpm: parts[8].parse().unwrap(),
+            bpm,
             repeats: parts[9].to_string(),
-        };
-        println!("Music:\n{}", song.music);
-        song
+            warnings: warnings.into_iter().map(|w| w.with_title(title)).collect(),
+        })
+    }
+
+    /// The song in iReal's `=` separated format, before percent escaping.
+    pub fn to_text(&self) -> String {
+        format!(
+            "{}={}=={}={}={}={}={}={}={}",
+            self.title,
+            self.composer,
+            self.style,
+            self.key,
+            self.transpose,
+            encode_music(&self.music),
+            self.comp_style,
+            self.bpm,
+            self.repeats
+        )
     }
 
     // Just turn this into a sequence of Chords
@@ -175,7 +348,39 @@ impl Song {
 }
 
 /* See https://loophole-letters.vercel.app/ireal-changes */
-pub fn parse_url(mut text: &str) -> Result<Collection, String> {
+pub fn parse_url(text: &str) -> Result<Collection, String> {
+    let (collection, _) = parse_url_with(text, &ParseOptions::default())?;
+    Ok(collection)
+}
+
+/// Read a collection. When lenient, songs that can't be read are left out
+/// and their errors returned alongside it, and music that can't be read
+/// is skipped with a warning on its song.
+pub fn parse_url_with(
+    text: &str,
+    options: &ParseOptions,
+) -> Result<(Collection, Vec<SongError>), String> {
+    let (title, results) = parse_url_songs(text, options)?;
+    let mut songs = vec![];
+    let mut errors = vec![];
+    for result in results {
+        match result {
+            Ok(song) => songs.push(song),
+            Err(error) if options.lenient => errors.push(error),
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok((Collection { title, songs }, errors))
+}
+
+/// The collection's title and each of its songs, read separately so that a
+/// song that can't be read doesn't stop the others. Only a URL that can't
+/// be read at all is an error. With the `rayon` feature, the songs are read
+/// in parallel.
+pub fn parse_url_songs(
+    mut text: &str,
+    options: &ParseOptions,
+) -> Result<(String, Vec<Result<Song, SongError>>), String> {
     text = text.trim();
     if !text.starts_with("irealb://") {
         return Err("Expected URL to start with 'irealb://'".to_string());
@@ -189,11 +394,25 @@ pub fn parse_url(mut text: &str) -> Result<Collection, String> {
     } else {
         "No Title"
     };
-    let songs = parts.into_iter().map(Song::from_text).collect();
-    Ok(Collection {
-        title: collection_title.to_string(),
-        songs,
-    })
+    let read = |(index, part)| Song::from_text(index, part, options);
+    #[cfg(feature = "rayon")]
+    let songs = parts.into_par_iter().enumerate().map(read).collect();
+    #[cfg(not(feature = "rayon"))]
+    let songs = parts.into_iter().enumerate().map(read).collect();
+    Ok((collection_title.to_string(), songs))
+}
+
+/// Read many collections, in the same order as `urls`. With the `rayon`
+/// feature, the collections and their songs are read in parallel.
+pub fn parse_urls<S: AsRef<str> + Sync>(
+    urls: &[S],
+    options: &ParseOptions,
+) -> Vec<Result<(Collection, Vec<SongError>), String>> {
+    let read = |url: &S| parse_url_with(url.as_ref(), options);
+    #[cfg(feature = "rayon")]
+    return urls.par_iter().map(read).collect();
+    #[cfg(not(feature = "rayon"))]
+    return urls.iter().map(read).collect();
 }
 
 #[cfg(test)]
@@ -216,15 +435,1300 @@ mod tests {
         assert_eq!(song.composer, "Monk Thelonious".to_string());
         assert_eq!(song.style, "Medium Swing".to_string());
         assert_eq!(song.key, "Db".to_string());
-        assert_eq!(format!("{}", song.music),
-"|: [A] 4/4    Db7                  |             %           |    Gb7                  |             %           |
-|     F7           E7      |    Eb7                  |     D7                  |             %           :|
-|| [B]  D7sus         G7b5      |   G7b5                  |   C7b5                  |             %           |
-|    Ch7                  |     F7                  |     E7           A7      | Ab7b9#5                  ||
-|| [C]    Db7                  |             %           |    Gb7                  |             %           |
-|     F7           E7      |    Eb7                  |     D7                  |             %            𝄌|
-| 𝄌      G                  |
-");
+        assert_eq!(
+            format!("{}", song.music),
+            "[A] 4/4
+|: Db7            |        %        | Gb7             |        %        |
+| F7      E7      | Eb7             | D7              |        %        :|
+[B]
+|| D7sus  G7b5    | G7b5            | C7b5            |        %        |
+| Ch7             | F7              | E7      A7      | Ab7b9#5         ||
+[C]
+|| Db7            |        %        | Gb7             |        %        |
+                                                      𝄌
+| F7      E7      | Eb7             | D7              |        %        |]
+
+𝄌
+| G               |]
+"
+        );
+    }
+
+    #[test]
+    fn build_and_encode() {
+        let f7 = Chord::basic(Note::F, Flavor::Dominant(Some(Number::Seven)));
+        let bb7 = Chord::basic(Note::BFlat, Flavor::Dominant(Some(Number::Seven)));
+        let g_7 = Chord::basic(Note::G, Flavor::Minor(Some(Number::Seven)));
+        let c7 = Chord::basic(Note::C, Flavor::Dominant(Some(Number::Seven)));
+        let music = MusicBuilder::new()
+            .section("A")
+            .repeat_start()
+            .chord(f7.clone(), 4)
+            .bar()
+            .chord(bb7.clone(), 4)
+            .bar()
+            .chord(f7.clone(), 4)
+            .bar()
+            .repeat_bar()
+            .bar()
+            .ending(1)
+            .chord(g_7, 2)
+            .chord(c7, 2)
+            .repeat_end()
+            .ending(2)
+            .chord(f7, 4)
+            .final_bar()
+            .build()
+            .unwrap();
+        assert_eq!(
+            music.raw,
+            "*A{T44F7   |Bb7   |F7   | x  |N1G-7 C7 }N2F7   Z"
+        );
+        assert_eq!(music.written_bars.len(), 6);
+        // Every bar, the bar repeat too, keeps to iReal's grid.
+        assert!(music.written_bars.iter().all(|bar| bar.cells.len() == 4));
+        let first_cells: Vec<usize> = music.layout().rows[0]
+            .bars
+            .iter()
+            .map(|bar| bar.first_cell)
+            .collect();
+        assert_eq!(first_cells, [0, 4, 8, 12]);
+
+        let song = SongBuilder::new("Exercise \u{e9}")
+            .composer("Doe Jane")
+            .key("F")
+            .bpm(120)
+            .music(music)
+            .build()
+            .unwrap();
+        let collection = Collection {
+            title: "Exercises".to_string(),
+            songs: vec![song],
+        };
+        assert_eq!(parse_url(&collection.to_url()).unwrap(), collection);
+
+        // Bars in 3/4 get a cell per beat, so durations survive the text.
+        let c = Chord::basic(Note::C, Flavor::Major(None));
+        let d = Chord::basic(Note::D, Flavor::Major(None));
+        let waltz = MusicBuilder::new()
+            .time_signature(3, 4)
+            .chord(c.clone(), 1)
+            .chord(d.clone(), 2)
+            .bar()
+            .chord(d, 3)
+            .bar()
+            .chord(c.clone(), 2)
+            .final_bar()
+            .build()
+            .unwrap();
+        assert_eq!(waltz.raw, "T34C^D^ |D^  |C^  Z");
+        let beats: Vec<u32> = waltz.timeline().iter().map(|t| t.beats).collect();
+        assert_eq!(beats, [1, 2, 3, 3]);
+
+        // iReal writes 12/8 as T12.
+        let twelve = MusicBuilder::new()
+            .time_signature(12, 8)
+            .chord(c, 12)
+            .final_bar()
+            .build()
+            .unwrap();
+        assert!(twelve.raw.starts_with("T12C^"));
+        assert_eq!(
+            twelve.timeline()[0].time_signature,
+            TimeSignature { top: 12, bottom: 8 }
+        );
+    }
+
+    #[test]
+    fn build_errors() {
+        let c = Chord::basic(Note::C, Flavor::Major(Some(Number::Seven)));
+        let overfull = MusicBuilder::new()
+            .chord(c.clone(), 3)
+            .chord(c.clone(), 2)
+            .build();
+        assert_eq!(
+            overfull.unwrap_err(),
+            "Bar 1: C^7 overfills the bar (3 + 2 beats in 4/4)"
+        );
+        let unmatched = MusicBuilder::new()
+            .repeat_start()
+            .chord(c.clone(), 4)
+            .build();
+        assert_eq!(unmatched.unwrap_err(), "Bar 2: Repeat is never ended");
+        let unstarted = MusicBuilder::new().chord(c.clone(), 4).repeat_end().build();
+        assert_eq!(
+            unstarted.unwrap_err(),
+            "Bar 1: Repeat ended without being started"
+        );
+        let odd_meter = MusicBuilder::new()
+            .time_signature(4, 5)
+            .chord(c.clone(), 4)
+            .build();
+        assert_eq!(odd_meter.unwrap_err(), "Bar 1: Invalid time signature 4/5");
+        let late_ending = MusicBuilder::new()
+            .chord(c.clone(), 2)
+            .ending(1)
+            .chord(c.clone(), 2)
+            .build();
+        assert_eq!(
+            late_ending.unwrap_err(),
+            "Bar 1: Ending must come at the beginning of a bar"
+        );
+        let late_section = MusicBuilder::new().chord(c.clone(), 2).section("A").build();
+        assert_eq!(
+            late_section.unwrap_err(),
+            "Bar 1: Section marker must come at the beginning of a bar"
+        );
+        let late_segno = MusicBuilder::new().chord(c, 2).segno().build();
+        assert_eq!(
+            late_segno.unwrap_err(),
+            "Bar 1: Segno must come at the beginning of a bar"
+        );
+    }
+
+    #[test]
+    fn chord_from_str() {
+        let cases = [
+            ("C^7", "C^7"),
+            ("Cmaj7", "C^7"),
+            ("CM7", "C^7"),
+            ("C\u{394}", "C^"),
+            ("C\u{394}9", "C^9"),
+            ("Cmaj", "C"),
+            ("Cm7", "C-7"),
+            ("Cmin", "C-"),
+            ("Cm(maj7)", "C-^7"),
+            ("C\u{f8}7", "Ch7"),
+            ("Cm7b5", "Ch7"),
+            ("C\u{b0}7", "Co7"),
+            ("Cdim", "Co"),
+            ("Caug", "C+"),
+            ("C6/9", "C69"),
+            ("Cm6/9", "C-69"),
+            ("C7(b9)", "C7b9"),
+            ("C7(b9,#11)", "C7b9#11"),
+            ("B\u{266d}7\u{266f}9", "Bb7#9"),
+            ("C13sus4", "C13sus"),
+            ("G7alt/D\u{266d}", "G7alt/Db"),
+            ("N.C.", "N.C."),
+        ];
+        for (text, expected) in cases {
+            let chord: Chord = text.parse().unwrap();
+            assert_eq!(chord.to_string(), expected, "parsing {:?}", text);
+        }
+        assert!("H7".parse::<Chord>().is_err());
+        assert!("C7(".parse::<Chord>().is_err());
+    }
+
+    #[test]
+    fn chord_styles() {
+        let key: Key = "Bb".parse().unwrap();
+        let styles = [
+            ChordStyle::IReal,
+            ChordStyle::Jazz,
+            ChordStyle::Plain,
+            ChordStyle::Nashville(key.clone()),
+            ChordStyle::Roman(key),
+        ];
+        let cases = [
+            (
+                "Bb^7",
+                [
+                    "Bb^7",
+                    "B\u{266d}\u{394}\u{2077}",
+                    "Bbmaj7",
+                    "1maj\u{2077}",
+                    "Imaj7",
+                ],
+            ),
+            (
+                "C-7",
+                ["C-7", "C\u{2212}\u{2077}", "Cm7", "2m\u{2077}", "ii7"],
+            ),
+            (
+                "F7b9",
+                [
+                    "F7b9",
+                    "F\u{2077}\u{266d}\u{2079}",
+                    "F7b9",
+                    "5\u{2077}\u{266d}\u{2079}",
+                    "V7b9",
+                ],
+            ),
+            (
+                "Ah7",
+                [
+                    "Ah7",
+                    "A\u{f8}\u{2077}",
+                    "Am7b5",
+                    "7\u{f8}\u{2077}",
+                    "vii\u{f8}7",
+                ],
+            ),
+            (
+                "Bo7",
+                [
+                    "Bo7",
+                    "B\u{b0}\u{2077}",
+                    "Bdim7",
+                    "b2\u{b0}\u{2077}",
+                    "bii\u{b0}7",
+                ],
+            ),
+            (
+                "Ab7/C",
+                [
+                    "Ab7/C",
+                    "A\u{266d}\u{2077}/C",
+                    "Ab7/C",
+                    "b7\u{2077}/2",
+                    "bVII7/2",
+                ],
+            ),
+        ];
+        for (text, expected) in cases {
+            let chord: Chord = text.parse().unwrap();
+            for (style, expected) in styles.iter().zip(expected) {
+                assert_eq!(chord.styled(style).to_string(), expected, "{:?}", style);
+            }
+        }
+    }
+
+    #[test]
+    fn roman_numerals() {
+        let key: Key = "C".parse().unwrap();
+        let cases = [
+            ("D-7", "ii7", HarmonicFunction::Diatonic),
+            ("G7", "V7", HarmonicFunction::Diatonic),
+            ("C^7", "Imaj7", HarmonicFunction::Diatonic),
+            ("D7", "V7/V", HarmonicFunction::SecondaryDominant(7)),
+            ("A7b9", "V7b9/ii", HarmonicFunction::SecondaryDominant(2)),
+            ("C7", "V7/IV", HarmonicFunction::SecondaryDominant(5)),
+            ("Bb7", "bVII7", HarmonicFunction::Borrowed),
+            ("F-6", "iv6", HarmonicFunction::Borrowed),
+            ("Dh7", "ii\u{f8}7", HarmonicFunction::Borrowed),
+            ("Db7", "bII7", HarmonicFunction::Chromatic),
+        ];
+        for (text, roman, function) in cases {
+            let chord: Chord = text.parse().unwrap();
+            let (label, _, f) = analyze_chord(&chord, &key).unwrap();
+            assert_eq!((label.as_str(), f), (roman, function), "{}", text);
+        }
+
+        let minor: Key = "C-".parse().unwrap();
+        let chord: Chord = "Dh7".parse().unwrap();
+        let (label, nashville, function) = analyze_chord(&chord, &minor).unwrap();
+        assert_eq!(label, "ii\u{f8}7");
+        assert_eq!(nashville, "2\u{f8}\u{2077}");
+        assert_eq!(function, HarmonicFunction::Diatonic);
+    }
+
+    #[test]
+    fn timeline() {
+        let text = "{*AT44Db7XyQKcl LZGb7XyQKcl LZF7 E7LZEb7XyQ|D7XyQKcl  }";
+        let music = parse::parse_music(text).unwrap();
+        let timeline: Vec<(String, usize, u32, u32)> = music
+            .timeline()
+            .into_iter()
+            .map(|t| (t.chord.to_string(), t.bar, t.beat, t.beats))
+            .collect();
+        let expected: Vec<(String, usize, u32, u32)> = [
+            ("Db7", 0, 0, 4),
+            ("Db7", 1, 0, 4),
+            ("Gb7", 2, 0, 4),
+            ("Gb7", 3, 0, 4),
+            ("F7", 4, 0, 2),
+            ("E7", 4, 2, 2),
+            ("Eb7", 5, 0, 4),
+            ("D7", 6, 0, 4),
+            ("D7", 7, 0, 4),
+        ]
+        .into_iter()
+        .map(|(c, bar, beat, beats)| (c.to_string(), bar, beat, beats))
+        .collect();
+        assert_eq!(timeline, expected);
+    }
+
+    #[test]
+    fn ending_measure_and_vertical_space() {
+        // The last line of All The Things You Are: the tune ends on the U
+        // bar, and the turnaround after it is for going round again.
+        let music = parse::parse_music(
+            "*D[F-7XyQ|Bb-7XyQ|Eb7XyQ|Ab^7XyQ|Db^7XyQ|Db-^7XyQ|C-7XyQ|Bo7XyQ|Bb-7XyQ|Eb7XyQ|UAb^7XyQ|Gh7 C7b9 Z",
+        )
+        .unwrap();
+        assert_eq!(music.written_bars.len(), 12);
+        assert!(music.written_bars[10]
+            .elements
+            .contains(&WrittenElement::EndingMeasure));
+        assert_eq!(music.playback_order().len(), 12);
+        assert_eq!(music.last_time_order(), (0..11).collect::<Vec<_>>());
+        assert!(music.layout().rows[2].bars[2].ending_measure);
+        assert!(music
+            .to_terminal(&TerminalOptions::default())
+            .contains("END"));
+
+        // A Nightingale Sang In Berkeley Square puts a spacer before the
+        // first ending, on its own line.
+        let music = parse::parse_music(
+            "{*AT44Eb^7 C-7LZG-7 sBb-7,Eb7,|lAb^7 sDh,G7,|lC- Ab-6LZEb^7/Bb F-7LZEb^7/G sAb-7,Db7,|lEb^7 C-7LZF-7 Bb7LZY|N1Eb^7 C-7LZF-7 Bb7 }N2Eb^7XyQ|Ah7 D7 ]",
+        )
+        .unwrap();
+        assert_eq!(music.written_bars.len(), 12);
+        assert_eq!(music.written_bars[8].vertical_space, 1);
+        let layout = music.layout();
+        assert_eq!(
+            layout.rows.iter().map(|r| r.space).collect::<Vec<_>>(),
+            [0, 0, 1]
+        );
+
+        // Spacers add room above the line the bar falls on.
+        let c = Chord::basic(Note::C, Flavor::Major(Some(Number::Seven)));
+        let mut builder = MusicBuilder::new();
+        for _ in 0..4 {
+            builder = builder.chord(c.clone(), 4).bar();
+        }
+        let music = builder
+            .vertical_space()
+            .vertical_space()
+            .chord(c, 4)
+            .final_bar()
+            .build()
+            .unwrap();
+        let rows = music.layout().rows;
+        assert_eq!((rows.len(), rows[1].space), (2, 2));
+        assert!(music
+            .to_terminal(&TerminalOptions::default())
+            .contains("|\n\n\n|"));
+    }
+
+    #[test]
+    fn patterns() {
+        let text = "[T44D-7 G7LZC^7XyQ|Dh7 G7b9LZC-6XyQ|D-7 Db7LZC^7XyQ|\
+                    C^7 A-7LZD-7 G7LZF-7 Bb7LZC^7XyQ|B7 E7LZA7 D7LZG7XyQ|C^7XyQZ";
+        let music = parse::parse_music(text).unwrap();
+        let found: Vec<(PatternKind, usize, u32, usize, String)> = music
+            .find_patterns()
+            .into_iter()
+            .map(|p| (p.kind, p.bar, p.beat, p.end_bar, p.key.to_string()))
+            .collect();
+        use PatternKind::*;
+        assert_eq!(
+            found,
+            vec![
+                (MajorTwoFiveOne, 0, 0, 1, "C".to_string()),
+                (MinorTwoFiveOne, 2, 0, 3, "C-".to_string()),
+                (TritoneTwoFiveOne, 4, 0, 5, "C".to_string()),
+                (Turnaround, 5, 0, 7, "C".to_string()),
+                (Backdoor, 8, 0, 9, "C".to_string()),
+                (Turnaround, 10, 0, 11, "G".to_string()),
+                (DominantChain(5), 10, 0, 12, "C".to_string()),
+                (Turnaround, 10, 2, 12, "C".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_regions() {
+        let text = "[T44C^7XyQ|A-7XyQ|D-7XyQ|G7XyQ|C^7XyQ|F^7XyQ|D-7 G7LZC^7XyQ]\
+                    [Eb^7XyQ|C-7XyQ|F-7XyQ|Bb7XyQ|Eb^7XyQ|Ab^7XyQ|F-7 Bb7LZEb^7XyQ]\
+                    [Dh7XyQ|G7b9XyQ|C-6XyQKcl Z";
+        let music = parse::parse_music(text).unwrap();
+        let regions = music.key_regions();
+        let found: Vec<(String, usize, usize)> = regions
+            .iter()
+            .map(|r| (r.key.to_string(), r.bar, r.end_bar))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("C".to_string(), 0, 7),
+                ("Eb".to_string(), 8, 15),
+                ("C-".to_string(), 16, 19),
+            ]
+        );
+        assert!(regions.iter().all(|r| r.confidence > 0.0));
+
+        let c9: Chord = "C7b9#11".parse().unwrap();
+        assert_eq!(c9.pitch_classes(), vec![0, 1, 4, 6, 7, 10]);
+    }
+
+    #[test]
+    fn forms() {
+        let work = "{*AT44Db7XyQKcl LZGb7XyQKcl LZF7 E7LZEb7XyQ|D7XyQKcl  }[*BD7sus G7b5LZG7b5XyQ|\
+                    C7b5XyQKcl LZCh7XyQ|F7XyQ|E7 A7LZAb7b9#5XyQ][*CDb7XyQKcl LZGb7XyQKcl LZF7 E7LZ\
+                    Eb7XyQ|D7XyQKcl Q ZY|QGXyQZ ";
+        let form = parse::parse_music(work).unwrap().form();
+        assert_eq!(form.kind, FormKind::AABA);
+        assert_eq!(
+            form.sections.iter().map(|s| s.bars).collect::<Vec<_>>(),
+            vec![8, 8, 8, 8]
+        );
+
+        let rhythm =
+            "{*AT44Bb^7 G7LZC-7 F7LZBb^7 G7LZC-7 F7LZF-7 Bb7LZEb^7 Ab7LZN1D-7 G7LZC-7 F7 }\
+                      N2C-7 F7LZBb6XyQ][*BD7XyQKcl LZG7XyQKcl LZC7XyQKcl LZF7XyQKcl ]\
+                      [*ABb^7 G7LZC-7 F7LZBb^7 G7LZC-7 F7LZF-7 Bb7LZEb^7 Ab7LZC-7 F7LZBb6XyQZ";
+        let form = parse::parse_music(rhythm).unwrap().form();
+        assert_eq!(form.kind, FormKind::RhythmChanges);
+        assert_eq!(form.letters(), "AABA");
+
+        let blues = "{*AT44F7XyQ|Bb7XyQ|F7XyQKcl LZBb7XyQKcl LZF7XyQ|D7XyQ|G-7XyQ|C7XyQ|\
+                     F7 D7LZG-7 C7 }";
+        let form = parse::parse_music(blues).unwrap().form();
+        assert_eq!(form.kind, FormKind::TwelveBarBlues);
+        assert_eq!(form.sections[0].bars, 12);
+
+        // Letters stop at Z.
+        let qualities = ["^7", "-7", "7", "h7", "o7", "+"];
+        let long: String = (0..30)
+            .map(|i| {
+                let root = ["C", "Db", "D", "Eb", "E", "F"][i / qualities.len()];
+                format!("*A{}{}XyQ|", root, qualities[i % qualities.len()])
+            })
+            .collect();
+        let form = parse::parse_music(&long).unwrap().form();
+        assert_eq!(
+            form.letters(),
+            format!("{}ZZZZ", ('A'..='Z').collect::<String>())
+        );
+        assert!(matches!(form.kind, FormKind::Other(_)));
+    }
+
+    #[test]
+    fn chord_scales() {
+        let music = parse::parse_music("[T44D-7 G7LZC^7XyQ|Bh7 E7b9LZA-7XyQ|Eb7altXyQZ").unwrap();
+        let found: Vec<(String, String, String)> = music
+            .chord_scales()
+            .into_iter()
+            .map(|c| {
+                let best = &c.scales[0];
+                let notes: Vec<String> = best.notes.iter().map(|n| n.to_string()).collect();
+                (c.chord.to_string(), best.scale.to_string(), notes.join(" "))
+            })
+            .collect();
+        let expected = [
+            ("D-7", "Dorian", "D E F G A B C"),
+            ("G7", "Mixolydian", "G A B C D E F"),
+            ("C^7", "Ionian", "C D E F G A B"),
+            ("Bh7", "Locrian #2", "B C# D E F G A"),
+            ("E7b9", "Phrygian dominant", "E F G# A B C D"),
+            ("A-7", "Aeolian", "A B C D E F G"),
+            ("Eb7alt", "Altered", "Eb E Gb G A Cb Db"),
+        ];
+        let expected: Vec<(String, String, String)> = expected
+            .iter()
+            .map(|(a, b, c)| (a.to_string(), b.to_string(), c.to_string()))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn voicings() {
+        let music = parse::parse_music("[T44D-7XyQ|G7XyQ|C^7XyQ|nXyQZ").unwrap();
+        let voiced = |style| -> Vec<String> {
+            let options = VoicingOptions {
+                style,
+                ..Default::default()
+            };
+            music
+                .voicings(&options)
+                .iter()
+                .map(|v| v.to_string())
+                .collect()
+        };
+        assert_eq!(
+            voiced(VoicingStyle::Rootless),
+            ["D-7: F3 A3 C4 E4", "G7: F3 A3 B3 E4", "C^7: E3 G3 B3 D4"]
+        );
+        assert_eq!(
+            voiced(VoicingStyle::Shell),
+            ["D-7: D3 C4 F4", "G7: G3 B3 F4", "C^7: C3 B3 E4"]
+        );
+        let so_what = music.voicings(&VoicingOptions {
+            style: VoicingStyle::SoWhat,
+            ..Default::default()
+        });
+        let intervals: Vec<u8> = so_what[0].notes.windows(2).map(|w| w[1] - w[0]).collect();
+        assert_eq!(intervals, [5, 5, 5, 4]);
+        for voicing in music.voicings(&VoicingOptions {
+            style: VoicingStyle::Drop2,
+            low: 40,
+            high: 80,
+        }) {
+            // The top voice of a drop 2 voicing is the melody, with the
+            // others spread over more than an octave below it.
+            assert_eq!(voicing.notes.len(), 4);
+            assert!(voicing.notes[3] - voicing.notes[0] > 12);
+        }
+    }
+
+    #[test]
+    fn guitar() {
+        let d_minor: Chord = "D-7".parse().unwrap();
+        let shapes = guitar_shapes(&d_minor, &Tuning::standard());
+        let frets = |kind| -> Vec<Vec<Option<u8>>> {
+            shapes
+                .iter()
+                .filter(|s| s.kind == kind)
+                .map(|s| s.frets.clone())
+                .collect()
+        };
+        assert!(frets(ShapeKind::RootSixth).contains(&vec![
+            Some(10),
+            None,
+            Some(10),
+            Some(10),
+            Some(10),
+            None
+        ]));
+        assert!(frets(ShapeKind::RootFifth).contains(&vec![
+            None,
+            Some(5),
+            Some(7),
+            Some(5),
+            Some(6),
+            None
+        ]));
+        assert!(frets(ShapeKind::RootFourth).contains(&vec![
+            None,
+            None,
+            Some(0),
+            Some(2),
+            Some(1),
+            Some(1)
+        ]));
+        for shape in &shapes {
+            let mut pitch_classes: Vec<u8> = shape
+                .notes(&Tuning::standard())
+                .iter()
+                .map(|n| n % 12)
+                .collect();
+            pitch_classes.sort();
+            pitch_classes.dedup();
+            assert_eq!(pitch_classes, [0, 2, 5, 9], "{:?}", shape);
+        }
+        assert_eq!(
+            shapes[0].to_ascii(),
+            "D-7\nx x o\n===========\n| | | | * *\n| | | * | |\n| | | | | |\n| | | | | |"
+        );
+        assert!(shapes[0].to_svg().starts_with("<svg"));
+        // Shapes built by hand can be empty or very high up the neck.
+        for frets in [vec![], vec![Some(255), None, Some(251)]] {
+            let shape = GuitarShape {
+                frets,
+                ..shapes[0].clone()
+            };
+            shape.to_ascii();
+            shape.to_svg();
+            shape.notes(&Tuning::standard());
+        }
+
+        let music = parse::parse_music("[T44D-7 G7LZC^7XyQZ").unwrap();
+        let chords = music.guitar_chords(&Tuning::standard());
+        assert_eq!(chords.len(), 3);
+        // Each change stays in the same area of the neck.
+        for pair in chords.windows(2) {
+            let position = |c: &GuitarChord| c.shape.base_fret();
+            assert!(position(&pair[0]).abs_diff(position(&pair[1])) <= 5);
+        }
+        let chart = music.guitar_chart(&Tuning::standard());
+        assert!(chart.starts_with("Bar 1\nD-7"));
+        assert!(chart.contains("Bar 2\nC^7"));
+    }
+
+    #[test]
+    fn layout() {
+        let music = parse::parse_music(
+            "*A[T44C^7 A-7LZD-7 G7LZE-7 A7LZD-7 G7 }|N1C^7XyQKcl LZN2C^7XyQ|QC7XyQ]*B[F^7XyQ|p(Bb7)pS,F-6 sZXyQXyQ  Y{QC^7XyQ|Bb7XyQ<D.S. al Coda>|A-7XyQ}",
+        )
+        .unwrap();
+        let layout = music.layout();
+        let rows: Vec<Vec<usize>> = layout
+            .rows
+            .iter()
+            .map(|row| row.bars.iter().map(|b| b.bar).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                vec![0, 1, 2, 3],
+                vec![4, 5, 6, 7],
+                vec![8, 9],
+                vec![10, 11, 12]
+            ]
+        );
+        let bar = |i: usize| &layout.rows[i / 4].bars[i % 4];
+        assert_eq!(
+            bar(0).time_signature,
+            Some(TimeSignature { top: 4, bottom: 4 })
+        );
+        assert_eq!(bar(0).section.as_deref(), Some("A"));
+        assert_eq!(bar(3).end, Barline::RepeatEnd);
+        // Two chords take half a bar each.
+        assert!(bar(0).cells[0].content.is_some() && bar(0).cells[2].content.is_some());
+        assert_eq!((bar(4).ending, bar(4).ending_bars), (Some(1), 2));
+        assert_eq!(bar(5).simile, 1);
+        assert_eq!((bar(6).ending, bar(6).ending_bars), (Some(2), 2));
+        assert_eq!(layout.rows[1].bars[3].end, Barline::Double);
+        assert_eq!(layout.rows[2].bars[1].end, Barline::Final);
+        assert!(layout.rows[1].bars[3].coda);
+        let slashes = &layout.rows[2].bars[1];
+        assert_eq!(slashes.cells[0].content, Some(CellContent::Slash));
+        assert_eq!(slashes.cells[0].alternate, Some("Bb7".parse().unwrap()));
+        assert!(slashes.segno);
+        let coda = &layout.rows[3].bars[0];
+        assert_eq!((coda.start, coda.coda), (Barline::RepeatStart, true));
+        assert_eq!(
+            layout.rows[3].bars[1].comments,
+            [Annotation::Direction {
+                jump: Jump::DalSegno,
+                until: Until::Coda,
+                text: "D.S. al Coda".to_string()
+            }]
+        );
+
+        let song = SongBuilder::new("Grid & Bars")
+            .composer("Tester")
+            .key("Eb")
+            .music(music)
+            .build()
+            .unwrap();
+        let svg = song.to_svg();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(">Grid &amp; Bars</text>"));
+        assert!(svg.contains("Key: E\u{266d}"));
+        assert!(svg.contains(">D.S. al Coda</text>"));
+        assert!(svg.contains(">B\u{266d}7</text>"));
+    }
+
+    #[test]
+    fn annotations() {
+        let parse = |text| Annotation::parse(text).0;
+        let direction = |jump, until, text: &str| Annotation::Direction {
+            jump,
+            until,
+            text: text.to_string(),
+        };
+        assert_eq!(
+            parse("D.C. al Coda"),
+            direction(Jump::DaCapo, Until::Coda, "D.C. al Coda")
+        );
+        assert_eq!(
+            parse("After solos, D.S. al fine"),
+            direction(Jump::DalSegno, Until::Fine, "After solos, D.S. al fine")
+        );
+        assert_eq!(
+            parse("D.C. al 2nd End."),
+            direction(Jump::DaCapo, Until::Ending(2), "D.C. al 2nd End.")
+        );
+        assert_eq!(
+            parse("D.C. on cue"),
+            direction(Jump::DaCapo, Until::End, "D.C. on cue")
+        );
+        for text in ["Fine", "FINE"] {
+            let fine = parse(text);
+            assert_eq!(
+                fine,
+                Annotation::Fine {
+                    text: text.to_string()
+                }
+            );
+            // Charts are shown as written.
+            assert_eq!(fine.to_string(), text);
+        }
+        for text in ["3x", "(3xs)", "x3", "Repeat 3x", "Repeat 3 times"] {
+            let count = parse(text);
+            assert_eq!(
+                count,
+                Annotation::RepeatCount {
+                    count: 3,
+                    text: text.to_string()
+                },
+                "{}",
+                text
+            );
+            assert_eq!(count.to_string(), text);
+        }
+        assert_eq!(
+            parse("3X (for solos only)"),
+            Annotation::Text("3X (for solos only)".to_string())
+        );
+        assert_eq!(
+            Annotation::parse("*74XyQSolos on Bb Blues"),
+            (
+                Annotation::Text("Solos on Bb Blues".to_string()),
+                Position {
+                    offset: Some(74),
+                    indent: 1
+                }
+            )
+        );
+        assert_eq!(
+            Annotation::parse("*664x"),
+            (
+                Annotation::RepeatCount {
+                    count: 4,
+                    text: "4x".to_string()
+                },
+                Position {
+                    offset: Some(66),
+                    indent: 0
+                }
+            )
+        );
+
+        // A repeat count on the closing bar is played.
+        let music = parse::parse_music("{C^7XyQ|G7 <3x>}|F^7XyQZ").unwrap();
+        assert_eq!(music.playback_order(), [0, 1, 0, 1, 0, 1, 2]);
+
+        let music = parse::parse_music("[C^7XyQ|G7 <*70D.S. al Coda>Z").unwrap();
+        assert_eq!(
+            music.written_bars[1].elements[1],
+            WrittenElement::Comment(
+                direction(Jump::DalSegno, Until::Coda, "D.S. al Coda"),
+                Position {
+                    offset: Some(70),
+                    indent: 0
+                }
+            )
+        );
+        assert!(music
+            .to_terminal(&TerminalOptions::default())
+            .ends_with("  D.S. al Coda\n"));
+    }
+
+    #[test]
+    fn terminal() {
+        let music = parse::parse_music("*A{T34C^7XyQ|A-7XyQ|N1D-7 G7 }|N2G7XyQ]").unwrap();
+        let options = TerminalOptions {
+            beat_width: 3,
+            ..TerminalOptions::default()
+        };
+        assert_eq!(
+            music.to_terminal(&options),
+            "[A] 3/4\n                      1._______  2._______\n\
+             |: C^7     | A-7      | D-7 G7   :| G7      ||\n"
+        );
+        let coloured = music.to_terminal(&TerminalOptions {
+            colour: true,
+            ..options
+        });
+        assert!(coloured.contains("\x1b[1;7m[A]\x1b[0m"));
+        assert!(coloured.contains("\x1b[32mA-7\x1b[0m"));
+        assert!(coloured.contains("\x1b[33mG7\x1b[0m"));
+        // Markers on a bar with an ending don't run into the next ending.
+        let music = parse::parse_music("*AN1T44C |N2D Z").unwrap();
+        assert_eq!(
+            music.to_terminal(&TerminalOptions::default()),
+            "[A] 4/4\n\
+             1.______________  2.______________\n\
+             | C               | D               |]\n"
+        );
+    }
+
+    #[test]
+    fn html() {
+        let music = parse::parse_music("*A{T34C^7XyQ|x|N1D-7 G7}|N2Bb7/DXyQ<Fine>]").unwrap();
+        let song = SongBuilder::new("Blues & Co")
+            .composer("Tester")
+            .key("Bb")
+            .music(music)
+            .build()
+            .unwrap();
+        let html = song.to_html();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<style>") && html.contains(CHART_CSS));
+        assert!(html.contains("<h1>Blues &amp; Co</h1>"));
+        assert!(html.contains(r#"<span class="section">A</span>"#));
+        assert!(html.contains(r#"<span class="time"><span>3</span><span>4</span></span>"#));
+        assert!(html.contains(r#"<span class="chord">C<sup>maj7</sup></span>"#));
+        assert!(html.contains("<span class=\"chord\">B\u{266d}<sup>7</sup>/D</span>"));
+        assert!(html.contains("<span class=\"repeat start\">\u{1d107}</span>"));
+        assert!(html.contains("<span class=\"simile\">\u{1d10d}</span>"));
+        assert!(html.contains(r#"<span class="ending" style="--span: 100%">2.</span>"#));
+        assert!(html.contains(r#"<span class="comment direction" style="--line: 0">Fine</span>"#));
+
+        let fragment = song.chart_html();
+        assert!(fragment.starts_with(r#"<div class="ireal-chart">"#));
+        assert!(!fragment.contains("<style>"));
+
+        let collection = Collection {
+            title: "Set".to_string(),
+            songs: vec![song.clone(), song],
+        };
+        assert_eq!(
+            collection.html_file_names(),
+            ["Blues___Co.html", "Blues___Co_2.html"]
+        );
+        assert_eq!(
+            collection.file_names("svg"),
+            ["Blues___Co.svg", "Blues___Co_2.svg"]
+        );
+        assert!(collection
+            .to_html_index()
+            .contains(r#"<li><a href="Blues___Co_2.html">Blues &amp; Co</a>"#));
+    }
+
+    #[test]
+    fn fake_book() {
+        let song = |title: &str, composer: &str, key: &str, music: &str| {
+            SongBuilder::new(title)
+                .composer(composer)
+                .key(key)
+                .music(parse::parse_music(music).unwrap())
+                .build()
+                .unwrap()
+        };
+        let collection = Collection {
+            title: "Book (Vol. 1)".to_string(),
+            songs: vec![
+                song("Zebra", "Adams", "F", "[T44F^7XyQ|Bb7(E7)XyQ|C7/E F6Z"),
+                song("Apple", "Brown", "C-", "{C-7XyQ|G7b9XyQ}"),
+            ],
+        };
+
+        let bb = collection.songs[0].transpose(Instrument::BFlat.semitones());
+        assert_eq!(bb.key, "G");
+        assert_eq!(bb.music.raw, "[T44G^7XyQ|C7(F#7)XyQ|D7/F# G6Z");
+        assert_eq!(bb.music, parse::parse_music(&bb.music.raw).unwrap());
+        let eb = collection.songs[1].transpose(Instrument::EFlat.semitones());
+        assert_eq!(eb.key, "A-");
+        assert_eq!(eb.music.raw, "{A-7XyQ|E7b9XyQ}");
+
+        let pdf = collection.to_pdf(&PdfOptions {
+            instrument: Instrument::BFlat,
+            order: TocOrder::Title,
+        });
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.ends_with("%%EOF\n"));
+        // Title page, contents and a page per song.
+        assert!(text.contains("/Count 4"));
+        assert!(text.contains("(Book \\(Vol. 1\\)) Tj"));
+        assert!(text.contains("(Bb Instruments) Tj"));
+        // Sorted by title, with Apple on page 3 and in A minor for Bb.
+        let apple = text.find("(Apple) Tj").unwrap();
+        let zebra = text.find("(Zebra) Tj").unwrap();
+        assert!(apple < zebra);
+        assert!(text.contains("Key: D-"));
+        // The cross reference table points at each object.
+        let xref: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(pdf[xref..].starts_with(b"xref"));
+        let xref_table = String::from_utf8(pdf[xref..].to_vec()).unwrap();
+        for line in xref_table
+            .lines()
+            .skip(3)
+            .take_while(|l| l.ends_with(" n "))
+        {
+            let offset: usize = line[..10].parse().unwrap();
+            let object = String::from_utf8_lossy(&pdf[offset..offset + 16]).to_string();
+            assert_eq!(object.split_whitespace().nth(2), Some("obj"));
+        }
+    }
+
+    #[test]
+    fn parts() {
+        let song = SongBuilder::new("Blues")
+            .key("Bb")
+            .music(parse::parse_music("{T44Bb7XyQ|Eb7XyQ|F7XyQ|Bb7XyQ}").unwrap())
+            .build()
+            .unwrap();
+        let collection = Collection {
+            title: "Band Book".to_string(),
+            songs: vec![song.clone()],
+        };
+        let keys: Vec<(Instrument, String, String)> =
+            ["concert", "tenor", "alto", "horn", "trombone"]
+                .iter()
+                .map(|name| {
+                    let part = song.part(name.parse().unwrap());
+                    (part.instrument, part.song.key, part.song.music.raw)
+                })
+                .collect();
+        assert_eq!(
+            keys,
+            [
+                (
+                    Instrument::Concert,
+                    "Bb".to_string(),
+                    "{T44Bb7XyQ|Eb7XyQ|F7XyQ|Bb7XyQ}".to_string()
+                ),
+                (
+                    Instrument::BFlat,
+                    "C".to_string(),
+                    "{T44C7XyQ|F7XyQ|G7XyQ|C7XyQ}".to_string()
+                ),
+                (
+                    Instrument::EFlat,
+                    "G".to_string(),
+                    "{T44G7XyQ|C7XyQ|D7XyQ|G7XyQ}".to_string()
+                ),
+                (
+                    Instrument::F,
+                    "F".to_string(),
+                    "{T44F7XyQ|Bb7XyQ|C7XyQ|F7XyQ}".to_string()
+                ),
+                (
+                    Instrument::BassClef,
+                    "Bb".to_string(),
+                    "{T44Bb7XyQ|Eb7XyQ|F7XyQ|Bb7XyQ}".to_string()
+                ),
+            ]
+        );
+        assert!("kazoo".parse::<Instrument>().is_err());
+
+        let horns = collection.part(Instrument::F);
+        assert_eq!(horns.title, "Band Book (F)");
+        assert_eq!(horns.songs[0].title, "Blues (F)");
+        let round_trip = parse_url(&horns.to_url()).unwrap();
+        assert_eq!(round_trip.songs[0].key, "F");
+        assert!(song
+            .part(Instrument::BassClef)
+            .to_svg()
+            .contains(">Bass Clef</text>"));
+        assert!(!song
+            .part(Instrument::Concert)
+            .to_svg()
+            .contains("Instruments"));
+
+        // Borrowed flat chords keep their flats in sharp and natural keys.
+        let flats = |key: &str, music: &str| {
+            SongBuilder::new("Flats")
+                .key(key)
+                .music(parse::parse_music(music).unwrap())
+                .build()
+                .unwrap()
+        };
+        let in_c = flats("C", "[T44C^7XyQ|Bb7XyQ|Ab7XyQ|G7/DXyQ|Eb^7XyQZ");
+        assert_eq!(in_c.part(Instrument::Concert).song, in_c);
+        assert_eq!(
+            in_c.part(Instrument::BFlat).song.music.raw,
+            "[T44D^7XyQ|C7XyQ|Bb7XyQ|A7/EXyQ|F^7XyQZ"
+        );
+        let book = Collection {
+            title: "Flat Book".to_string(),
+            songs: vec![in_c.clone()],
+        };
+        let concert = book.part(Instrument::Concert).to_url();
+        assert_eq!(concert, book.to_url());
+        assert_eq!(parse_url(&concert).unwrap(), book);
+        let bass = book.part(Instrument::BassClef);
+        assert_eq!(bass.songs[0].music, in_c.music);
+        let in_bb = flats("Bb", "[T44Bb^7XyQ|Ab7XyQ|Gb^7XyQ|A7XyQZ");
+        assert_eq!(
+            in_bb.part(Instrument::BFlat).song.music.raw,
+            "[T44C^7XyQ|Bb7XyQ|Ab^7XyQ|B7XyQZ"
+        );
+        assert_eq!(
+            in_bb.part(Instrument::EFlat).song.music.raw,
+            "[T44G^7XyQ|F7XyQ|Eb^7XyQ|F#7XyQZ"
+        );
+    }
+
+    #[test]
+    fn cells() {
+        // The opening of Autumn Leaves: commas take no cell, LZ is a space
+        // then a barline, and XyQ is three empty cells.
+        let music = parse::parse_music("*A{T44C-7 F7LZBb^7XyQ|Eb^7,XyQ,A7b9 }").unwrap();
+        let chords = |bar: &WrittenBar| -> Vec<(usize, String)> {
+            bar.cells
+                .iter()
+                .enumerate()
+                .filter_map(|(i, cell)| match &cell.content {
+                    Some(CellContent::Chord(chord, _)) => Some((i, chord.to_string())),
+                    _ => None,
+                })
+                .collect()
+        };
+        let bars = &music.written_bars;
+        assert_eq!(chords(&bars[0]), [(0, "C-7".into()), (2, "F7".into())]);
+        assert_eq!(chords(&bars[1]), [(0, "Bb^7".to_string())]);
+        assert_eq!(bars[1].cells.len(), 4);
+        assert_eq!(chords(&bars[2]), [(0, "Eb^7".into()), (4, "A7b9".into())]);
+        assert_eq!(bars[2].cells.len(), 6);
+
+        // Empty cells between bars leave a gap in the row.
+        let music = parse::parse_music("{C^7XyQ}XyQXyQ LZN2D-7XyQ]").unwrap();
+        assert_eq!(music.written_bars.len(), 2);
+        assert_eq!(music.written_bars[1].gap, 8);
+        let row = &music.layout().rows[0];
+        assert_eq!(row.bars[1].first_cell, 12);
+        assert!(row.shows_end(0));
+
+        // Chords last until the next one, counted in cells.
+        let music = parse::parse_music("C^7  A7|D-7XyQZ").unwrap();
+        let beats: Vec<u32> = music.timeline().iter().map(|t| t.beats).collect();
+        assert_eq!(beats, [3, 1, 4]);
+
+        // Music written back from its bars reads the same, cell for cell.
+        let content = std::fs::read_to_string("src/tests/data/jazz1460.url").unwrap();
+        for song in parse_url(&content).unwrap().songs {
+            let music = Music::from_bars(song.music.written_bars.clone());
+            let reparsed = parse::parse_music(&music.raw).unwrap();
+            assert_eq!(
+                reparsed.written_bars, song.music.written_bars,
+                "{}",
+                song.title
+            );
+        }
+    }
+
+    #[test]
+    fn syntax() {
+        let text = "{*AC^7 LZ|D-7,G7||XyQKcl LZ}|";
+        let tree = SyntaxTree::parse(text).unwrap();
+        let pieces: Vec<&str> = tree.nodes().iter().map(|n| tree.node_text(n)).collect();
+        assert_eq!(
+            pieces,
+            ["{", "*A", "C^7", " ", "LZ|", "D-7", ",", "G7", "||", "XyQ", "Kcl", " ", "LZ", "}|"]
+        );
+        assert_eq!(tree.nodes()[4].tokens, [Token::Space, Token::Bar]);
+        assert_eq!(tree.nodes()[8].tokens, [Token::Bar]);
+        let tokens: Vec<Token> = tokenize::tokenize(text)
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect();
+        assert_eq!(tree.tokens(), tokens);
+
+        // Editing one piece leaves the rest of the text alone.
+        let mut tree = tree;
+        tree.replace(2, "Eb^7").unwrap();
+        assert_eq!(tree.text(), "{*AEb^7 LZ|D-7,G7||XyQKcl LZ}|");
+        assert_eq!(tree.node_text(&tree.nodes()[13]), "}|");
+        assert!(tree.replace(2, "?").is_err());
+
+        let music = parse::parse_music("{C^7 LZ||D-7 G7}|").unwrap();
+        assert_eq!(music.transpose(2, false).raw, "{D^7 LZ||E-7 A7}|");
+
+        // Every byte of every chart is kept.
+        let content = std::fs::read_to_string("src/tests/data/jazz1460.url").unwrap();
+        for song in parse_url(&content).unwrap().songs {
+            let tree = SyntaxTree::parse(&song.music.raw).unwrap();
+            let text: String = tree.nodes().iter().map(|n| tree.node_text(n)).collect();
+            assert_eq!(text, song.music.raw);
+        }
+    }
+
+    #[test]
+    fn music_errors() {
+        let spans: Vec<(Token, std::ops::Range<usize>)> = tokenize::tokenize("C^7 LZ")
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.token, s.span))
+            .collect();
+        let c = Chord::basic(Note::C, Flavor::Major(Some(Number::Seven)));
+        assert_eq!(
+            spans,
+            [
+                (Token::Chord(c), 0..3),
+                (Token::Space, 3..4),
+                (Token::Space, 4..6),
+                (Token::Bar, 4..6)
+            ]
+        );
+
+        let error = parse::parse_music("C^7 A-7?|D-7XyQ").unwrap_err();
+        assert_eq!((error.offset, error.column), (7, 7));
+        assert_eq!(
+            error.to_string(),
+            "Unrecognized music at byte 7\n  C^7 A-7?|D-7XyQ\n         ^"
+        );
+        // Long charts are cut down to the music around the problem.
+        let error =
+            parse::parse_music("C^7XyQ|C^7XyQ|C^7XyQ|T4XyQ|C^7XyQ|C^7XyQ|C^7XyQ|").unwrap_err();
+        assert_eq!(error.offset, 21);
+        assert_eq!(error.snippet, "^7XyQ|C^7XyQ|C^7XyQ|T4XyQ|C^7XyQ|C^7XyQ|");
+        assert_eq!(error.column, 20);
+
+        // Errors in a collection name the song.
+        let mut song = SongBuilder::new("Bad Chart")
+            .music(parse::parse_music("C^7XyQZ").unwrap())
+            .build()
+            .unwrap();
+        song.music.raw = "C^7XyQ|N?XyQZ".to_string();
+        let collection = Collection {
+            title: "Broken".to_string(),
+            songs: vec![song],
+        };
+        assert_eq!(
+            parse_url(&collection.to_url()).unwrap_err(),
+            "Song 1 \"Bad Chart\": Unrecognized music at byte 7\n  C^7XyQ|N?XyQZ\n         ^"
+        );
+    }
+
+    #[test]
+    fn lenient() {
+        let text = "C^7 ?!XyQ|D-7&XyQZ";
+        assert!(parse::parse_music(text).is_err());
+        let options = ParseOptions { lenient: true };
+        let (music, warnings) = parse::read_music(text.to_string(), &options).unwrap();
+        assert_eq!(music.raw, text);
+        assert_eq!(music.written_bars.len(), 2);
+        assert_eq!(music.written_bars[1].cells.len(), 4);
+        assert_eq!(
+            warnings.iter().map(|w| w.offset).collect::<Vec<_>>(),
+            [4, 13]
+        );
+        let (tokens, _) = tokenize::tokenize_lenient(text);
+        assert_eq!(tokens[2].token, Token::Unknown("?!".into()));
+        assert_eq!(tokens[2].span, 4..6);
+        assert_eq!(music.transpose(2, false).raw, "D^7 ?!XyQ|E-7&XyQZ");
+
+        // Songs that can't be read are left out, and the rest still load.
+        let mut song = SongBuilder::new("Typo")
+            .music(parse::parse_music("C^7XyQZ").unwrap())
+            .build()
+            .unwrap();
+        song.music.raw = "C^7 ?XyQZ".to_string();
+        let text = format!("{}===Broken=Nobody===Lenient", song.to_text());
+        let url = format!("irealb://{}", escape_percent(&text));
+        assert!(parse_url(&url).is_err());
+        let (collection, errors) = parse_url_with(&url, &options).unwrap();
+        assert_eq!(collection.songs.len(), 1);
+        assert_eq!(collection.songs[0].warnings.len(), 1);
+        assert_eq!(
+            collection.songs[0].warnings[0].title.as_deref(),
+            Some("Typo")
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "Song 2 \"Broken\": 2 fields, expected 10"
+        );
+    }
+
+    #[test]
+    fn song_results() {
+        let good = SongBuilder::new("Good")
+            .music(parse::parse_music("C^7XyQZ").unwrap())
+            .build()
+            .unwrap();
+        let mut bad_music = good.clone();
+        bad_music.title = "Bad Music".to_string();
+        bad_music.music.raw = "C^7 ?XyQZ".to_string();
+        let text = format!(
+            "{}==={}====Nobody===Playlist",
+            good.to_text(),
+            bad_music.to_text()
+        );
+        let url = format!("irealb://{}", escape_percent(&text));
+        let (title, songs) = parse_url_songs(&url, &ParseOptions::default()).unwrap();
+        assert_eq!(title, "Playlist");
+        assert_eq!(songs.len(), 3);
+        assert_eq!(songs[0].as_ref().unwrap(), &good);
+
+        let error = songs[1].as_ref().unwrap_err();
+        assert_eq!(error.index, 1);
+        let music = error.music.as_ref().unwrap();
+        assert_eq!(
+            (music.offset, music.title.as_deref()),
+            (4, Some("Bad Music"))
+        );
+        assert_eq!(
+            error.to_string(),
+            "Song 2 \"Bad Music\": Unrecognized music at byte 4\n  C^7 ?XyQZ\n      ^"
+        );
+
+        // A song with no title.
+        let error = songs[2].as_ref().unwrap_err();
+        assert_eq!((error.index, error.title.as_ref()), (2, None));
+        assert_eq!(error.to_string(), "Song 3: 2 fields, expected 10");
+
+        assert!(parse_url_songs("http://example.com", &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn batch() {
+        let content = std::fs::read_to_string("src/tests/data/jazz1460.url").unwrap();
+        let jazz = parse_url(&content).unwrap();
+        let small = Collection {
+            title: "Small".to_string(),
+            songs: jazz.songs[..3].to_vec(),
+        };
+        let urls = [content.clone(), "not a url".to_string(), small.to_url()];
+        let results = parse_urls(&urls, &ParseOptions::default());
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().0, jazz);
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap().0, small);
+    }
+
+    #[test]
+    fn stream() {
+        // Hands out a few bytes at a time, so escapes and separators are cut
+        // across reads.
+        struct Trickle<'a>(&'a [u8]);
+        impl std::io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.0.len().min(buf.len()).min(5);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let options = ParseOptions::default();
+        let content = std::fs::read_to_string("src/tests/data/jazz1460.url").unwrap();
+        let jazz = parse_url(&content).unwrap();
+        let mut songs = SongIter::from_text(&content, &options);
+        assert_eq!(songs.title(), None);
+        let read: Vec<Song> = songs.by_ref().map(Result::unwrap).collect();
+        assert_eq!(read, jazz.songs);
+        assert_eq!(songs.title(), Some(jazz.title.as_str()));
+
+        let small = Collection {
+            title: "Small".to_string(),
+            songs: jazz.songs[..20].to_vec(),
+        };
+        let url = format!("\n {} \n", small.to_url());
+        let mut songs = SongIter::new(Trickle(url.as_bytes()), &options);
+        let read: Vec<Song> = songs.by_ref().map(Result::unwrap).collect();
+        assert_eq!(read, small.songs);
+        assert_eq!(songs.title(), Some("Small"));
+
+        // A single song has no collection title.
+        let url = small.to_url();
+        let single = &url[..url.find("===").unwrap()];
+        let mut songs = SongIter::from_text(single, &options);
+        assert_eq!(songs.next().unwrap().unwrap(), small.songs[0]);
+        assert!(songs.next().is_none());
+        assert_eq!(songs.title(), Some("No Title"));
+
+        // A bad song doesn't stop the ones after it.
+        let url = format!("irealb://Broken==={}", &small.to_url()[9..]);
+        let results: Vec<_> = SongIter::from_text(&url, &options).collect();
+        assert_eq!(results.len(), 21);
+        assert_eq!(results[0].as_ref().unwrap_err().index, 0);
+        assert_eq!(results[1].as_ref().unwrap(), &small.songs[0]);
+
+        // Latin-1 escapes are read as Latin-1 instead of failing the URL.
+        let mut cafe = small.songs[0].clone();
+        cafe.title = "Cafe".to_string();
+        let url = Collection {
+            title: "Small".to_string(),
+            songs: vec![cafe, small.songs[1].clone()],
+        }
+        .to_url()
+        .replacen("Cafe", "Caf%E9", 1);
+        let read = parse_url(&url).unwrap();
+        assert_eq!(read.songs[0].title, "Caf\u{e9}");
+        let streamed: Vec<Song> = SongIter::from_text(&url, &options)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(streamed, read.songs);
+
+        let mut songs = SongIter::from_text("http://example.com", &options);
+        assert!(songs.next().unwrap().is_err());
+        assert!(songs.next().is_none());
     }
 
     #[test]
@@ -0,0 +1,190 @@
+use std::{
+    env, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process,
+};
+
+use ireal_url::{
+    file_name, parse_url_with, Collection, Instrument, ParseOptions, PdfOptions, TocOrder,
+};
+
+const USAGE: &str = "Usage: ireal-url [options] FILE
+
+Reads an irealb:// URL from FILE, or from standard input if FILE is -, and
+writes a part for each instrument into the output directory.
+
+Options:
+  -p, --part INSTRUMENT  concert, bb, eb, f or bass. May be repeated.
+                         Defaults to concert.
+  -f, --format FORMAT    url, pdf, svg or html. Defaults to url.
+  -o, --output DIR       Where to write the parts. Defaults to the current
+                         directory.
+      --order ORDER      Fake book order for pdf: title or composer.
+      --lenient          Skip songs and music that can't be read, with a
+                         warning, rather than stopping.
+  -h, --help             Show this message.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Url,
+    Pdf,
+    Svg,
+    Html,
+}
+
+struct Options {
+    input: String,
+    parts: Vec<Instrument>,
+    format: Format,
+    output: PathBuf,
+    order: TocOrder,
+    lenient: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        input: String::new(),
+        parts: vec![],
+        format: Format::Url,
+        output: PathBuf::from("."),
+        order: TocOrder::Title,
+        lenient: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "-p" | "--part" => {
+                for part in value(arg)?.split(',') {
+                    options.parts.push(part.parse()?);
+                }
+            }
+            "-f" | "--format" => {
+                options.format = match value(arg)?.as_str() {
+                    "url" => Format::Url,
+                    "pdf" => Format::Pdf,
+                    "svg" => Format::Svg,
+                    "html" => Format::Html,
+                    other => return Err(format!("Unknown format: {:?}", other)),
+                }
+            }
+            "-o" | "--output" => options.output = PathBuf::from(value(arg)?),
+            "--order" => {
+                options.order = match value(arg)?.as_str() {
+                    "title" => TocOrder::Title,
+                    "composer" => TocOrder::Composer,
+                    other => return Err(format!("Unknown order: {:?}", other)),
+                }
+            }
+            "--lenient" => options.lenient = true,
+            other if other.starts_with('-') && other != "-" => {
+                return Err(format!("Unknown option: {}", other))
+            }
+            other => {
+                if !options.input.is_empty() {
+                    return Err("Only one input file can be given".to_string());
+                }
+                options.input = other.to_string();
+            }
+        }
+    }
+    if options.input.is_empty() {
+        return Err("No input file given".to_string());
+    }
+    if options.parts.is_empty() {
+        options.parts.push(Instrument::Concert);
+    }
+    Ok(options)
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn write_part(
+    collection: &Collection,
+    instrument: Instrument,
+    options: &Options,
+) -> Result<(), String> {
+    let base = file_name(&format!("{} {}", collection.title, instrument));
+    match options.format {
+        Format::Url => {
+            let url = collection.part(instrument).to_url();
+            write(&options.output.join(base + ".url"), url.as_bytes())
+        }
+        Format::Pdf => {
+            let pdf = collection.to_pdf(&PdfOptions {
+                instrument,
+                order: options.order,
+            });
+            write(&options.output.join(base + ".pdf"), &pdf)
+        }
+        Format::Svg => {
+            let dir = options.output.join(base);
+            fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+            for (song, name) in collection.songs.iter().zip(collection.file_names("svg")) {
+                let svg = song.part(instrument).to_svg();
+                write(&dir.join(name), svg.as_bytes())?;
+            }
+            Ok(())
+        }
+        Format::Html => {
+            let dir = options.output.join(base);
+            fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+            let part = collection.part(instrument);
+            write(&dir.join("index.html"), part.to_html_index().as_bytes())?;
+            for (song, name) in collection.songs.iter().zip(part.html_file_names()) {
+                let html = song.part(instrument).to_html();
+                write(&dir.join(name), html.as_bytes())?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let options = parse_args(args)?;
+    let mut text = String::new();
+    if options.input == "-" {
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("stdin: {}", e))?;
+    } else {
+        text =
+            fs::read_to_string(&options.input).map_err(|e| format!("{}: {}", options.input, e))?;
+    }
+    let parse_options = ParseOptions {
+        lenient: options.lenient,
+    };
+    let (collection, errors) = parse_url_with(&text, &parse_options)?;
+    for error in &errors {
+        eprintln!("ireal-url: warning: {}", error);
+    }
+    for warning in collection.songs.iter().flat_map(|song| &song.warnings) {
+        eprintln!("ireal-url: warning: {}", warning);
+    }
+    fs::create_dir_all(&options.output)
+        .map_err(|e| format!("{}: {}", options.output.display(), e))?;
+    for &instrument in &options.parts {
+        write_part(&collection, instrument, &options)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(error) = run(&args) {
+        eprintln!("ireal-url: {}", error);
+        eprintln!("Try 'ireal-url --help' for more information.");
+        process::exit(1);
+    }
+}
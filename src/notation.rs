@@ -0,0 +1,255 @@
+use std::fmt;
+
+use crate::types::{AlteredNotes, Chord, Flavor, Key, Note, Number};
+
+/// How to write chord symbols.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChordStyle {
+    /// iReal's own notation, the same as `Chord`'s Display: "Bb^7", "Dh7".
+    IReal,
+    /// Jazz chart symbols: "B♭Δ⁷", "Dø⁷", "F♯°⁷", "G⁷♭⁹".
+    Jazz,
+    /// Plain ASCII: "Bbmaj7", "Dm7b5", "F#dim7".
+    Plain,
+    /// Nashville numbers relative to the key: "1maj⁷", "2m⁷", "5⁷".
+    Nashville(Key),
+    /// Roman numerals relative to the key: "Imaj7", "ii7", "V7", "viiø7".
+    Roman(Key),
+}
+
+impl Chord {
+    /// Display the chord in the given style.
+    pub fn styled<'a>(&'a self, style: &'a ChordStyle) -> StyledChord<'a> {
+        StyledChord { chord: self, style }
+    }
+}
+
+pub struct StyledChord<'a> {
+    chord: &'a Chord,
+    style: &'a ChordStyle,
+}
+
+impl fmt::Display for StyledChord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (root, flavor, altered_notes, bass_note) = match self.chord {
+            Chord::NC => return write!(f, "N.C."),
+            Chord::Some {
+                root,
+                flavor,
+                altered_notes,
+                bass_note,
+            } => (root, flavor, altered_notes, bass_note),
+        };
+        match self.style {
+            ChordStyle::IReal => write!(f, "{}", self.chord),
+            ChordStyle::Jazz => {
+                if *root != Note::W {
+                    write!(f, "{}", glyphs(&root.to_string()))?;
+                }
+                write!(f, "{}", jazz_flavor(flavor))?;
+                for altered in altered_notes {
+                    write!(f, "{}", jazz_altered(altered))?;
+                }
+                if let Some(bass) = bass_note {
+                    write!(f, "/{}", glyphs(&bass.to_string()))?;
+                }
+                Ok(())
+            }
+            ChordStyle::Plain => {
+                if *root != Note::W {
+                    write!(f, "{}", root)?;
+                }
+                write!(f, "{}", plain_flavor(flavor))?;
+                for altered in altered_notes {
+                    write!(f, "{}", plain_altered(altered))?;
+                }
+                if let Some(bass) = bass_note {
+                    write!(f, "/{}", bass)?;
+                }
+                Ok(())
+            }
+            ChordStyle::Nashville(key) => {
+                if let Some((accidental, number)) = degree(key, root) {
+                    write!(f, "{}{}", accidental, number)?;
+                }
+                write!(f, "{}", nashville_flavor(flavor))?;
+                for altered in altered_notes {
+                    write!(f, "{}", jazz_altered(altered))?;
+                }
+                if let Some((accidental, number)) = bass_note.as_ref().and_then(|b| degree(key, b))
+                {
+                    write!(f, "/{}{}", accidental, number)?;
+                }
+                Ok(())
+            }
+            ChordStyle::Roman(key) => {
+                if let Some((accidental, number)) = degree(key, root) {
+                    let numeral = ROMAN[number as usize - 1];
+                    if is_minor_quality(flavor) {
+                        write!(f, "{}{}", accidental, numeral.to_lowercase())?;
+                    } else {
+                        write!(f, "{}{}", accidental, numeral)?;
+                    }
+                }
+                write!(f, "{}", roman_flavor(flavor))?;
+                for altered in altered_notes {
+                    write!(f, "{}", plain_altered(altered))?;
+                }
+                if let Some((accidental, number)) = bass_note.as_ref().and_then(|b| degree(key, b))
+                {
+                    write!(f, "/{}{}", accidental, number)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub(crate) const ROMAN: [&str; 7] = ["I", "II", "III", "IV", "V", "VI", "VII"];
+
+/* Scale degree of `note` in `key`, as an accidental and a number from 1 to 7.
+ * Degrees are always relative to the major scale of the tonic, so in C minor
+ * an Eb chord is bIII. */
+pub(crate) fn degree(key: &Key, note: &Note) -> Option<(&'static str, u8)> {
+    let tonic = key.tonic.pitch_class()?;
+    let interval = (note.pitch_class()? + 12 - tonic) % 12;
+    Some(match interval {
+        0 => ("", 1),
+        1 => ("b", 2),
+        2 => ("", 2),
+        3 => ("b", 3),
+        4 => ("", 3),
+        5 => ("", 4),
+        6 => ("#", 4),
+        7 => ("", 5),
+        8 => ("b", 6),
+        9 => ("", 6),
+        10 => ("b", 7),
+        _ => ("", 7),
+    })
+}
+
+fn is_minor_quality(flavor: &Flavor) -> bool {
+    matches!(
+        flavor,
+        Flavor::Minor(_)
+            | Flavor::MinorMajor(_)
+            | Flavor::MinorSixthNinth
+            | Flavor::HalfDiminished(_)
+            | Flavor::Diminished(_)
+            | Flavor::DiminishedMajor(_)
+    )
+}
+
+fn number(n: &Option<Number>) -> String {
+    match n {
+        Some(n) => n.to_string(),
+        None => String::new(),
+    }
+}
+
+/// Replace ASCII accidentals with their music glyphs.
+fn glyphs(text: &str) -> String {
+    text.replace('b', "\u{266d}").replace('#', "\u{266f}")
+}
+
+fn superscript(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '0' => '\u{2070}',
+            '1' => '\u{b9}',
+            '2' => '\u{b2}',
+            '3' => '\u{b3}',
+            '4' => '\u{2074}',
+            '5' => '\u{2075}',
+            '6' => '\u{2076}',
+            '7' => '\u{2077}',
+            '8' => '\u{2078}',
+            '9' => '\u{2079}',
+            '/' => '\u{2044}',
+            c => c,
+        })
+        .collect()
+}
+
+fn jazz_flavor(flavor: &Flavor) -> String {
+    match flavor {
+        Flavor::Augmented(n) => format!("+{}", superscript(&number(n))),
+        Flavor::Diminished(n) => format!("\u{b0}{}", superscript(&number(n))),
+        Flavor::DiminishedMajor(n) => format!("\u{b0}\u{394}{}", superscript(&number(n))),
+        Flavor::HalfDiminished(n) => format!("\u{f8}{}", superscript(&number(n))),
+        Flavor::Minor(n) => format!("\u{2212}{}", superscript(&number(n))),
+        Flavor::MinorMajor(n) => format!("\u{2212}\u{394}{}", superscript(&number(n))),
+        Flavor::Dominant(n) => superscript(&number(n)),
+        Flavor::Major(n) => format!("\u{394}{}", superscript(&number(n))),
+        Flavor::SixthNinth => superscript("6/9"),
+        Flavor::MinorSixthNinth => format!("\u{2212}{}", superscript("6/9")),
+    }
+}
+
+fn nashville_flavor(flavor: &Flavor) -> String {
+    match flavor {
+        Flavor::Minor(n) => format!("m{}", superscript(&number(n))),
+        Flavor::Major(None) => format!("maj{}", superscript("7")),
+        Flavor::Major(Some(n)) => format!("maj{}", superscript(&n.to_string())),
+        _ => jazz_flavor(flavor),
+    }
+}
+
+fn jazz_altered(altered: &AlteredNotes) -> String {
+    match altered {
+        AlteredNotes::Flat(n) => format!("\u{266d}{}", superscript(&n.to_string())),
+        AlteredNotes::Sharp(n) => format!("\u{266f}{}", superscript(&n.to_string())),
+        AlteredNotes::Add(n) => format!("add{}", superscript(&n.to_string())),
+        AlteredNotes::Sus => "sus".to_string(),
+        AlteredNotes::Alt => "alt".to_string(),
+        AlteredNotes::Custom(s) => s.to_string(),
+    }
+}
+
+fn plain_flavor(flavor: &Flavor) -> String {
+    match flavor {
+        Flavor::Augmented(n) => format!("aug{}", number(n)),
+        Flavor::Diminished(n) => format!("dim{}", number(n)),
+        Flavor::DiminishedMajor(_) => "dim(maj7)".to_string(),
+        Flavor::HalfDiminished(None) => "m7b5".to_string(),
+        Flavor::HalfDiminished(Some(n)) => format!("m{}b5", n),
+        Flavor::Minor(n) => format!("m{}", number(n)),
+        Flavor::MinorMajor(None) => "mMaj7".to_string(),
+        Flavor::MinorMajor(Some(n)) => format!("mMaj{}", n),
+        Flavor::Dominant(n) => number(n),
+        // A triangle on its own is a major seventh.
+        Flavor::Major(None) => "maj7".to_string(),
+        Flavor::Major(Some(n)) => format!("maj{}", n),
+        Flavor::SixthNinth => "6/9".to_string(),
+        Flavor::MinorSixthNinth => "m6/9".to_string(),
+    }
+}
+
+fn plain_altered(altered: &AlteredNotes) -> String {
+    match altered {
+        AlteredNotes::Custom(s) => s.to_string(),
+        _ => altered.to_string(),
+    }
+}
+
+/* Roman numerals already show minor by their case, so the quality only needs
+ * the extension and the symbols that case can't express. */
+fn roman_flavor(flavor: &Flavor) -> String {
+    match flavor {
+        Flavor::Augmented(n) => format!("+{}", number(n)),
+        Flavor::Diminished(n) => format!("\u{b0}{}", number(n)),
+        Flavor::DiminishedMajor(_) => "\u{b0}maj7".to_string(),
+        Flavor::HalfDiminished(None) => "\u{f8}7".to_string(),
+        Flavor::HalfDiminished(Some(n)) => format!("\u{f8}{}", n),
+        Flavor::Minor(n) => number(n),
+        Flavor::MinorMajor(None) => "maj7".to_string(),
+        Flavor::MinorMajor(Some(n)) => format!("maj{}", n),
+        Flavor::Dominant(n) => number(n),
+        // A triangle on its own is a major seventh.
+        Flavor::Major(None) => "maj7".to_string(),
+        Flavor::Major(Some(n)) => format!("maj{}", n),
+        Flavor::SixthNinth => "6/9".to_string(),
+        Flavor::MinorSixthNinth => "6/9".to_string(),
+    }
+}
@@ -1,7 +1,8 @@
-use std::{fmt, vec};
+use std::{iter, vec};
 
 use crate::{
-    tokenize::{self, Token, Width},
+    annotation::{Annotation, Position},
+    tokenize::{self, MusicError, Token, Width},
     types::{Chord, TimeSignature},
 };
 
@@ -11,19 +12,6 @@ pub struct Music {
     pub written_bars: Vec<WrittenBar>,
 }
 
-impl fmt::Display for Music {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (i, bar) in self.written_bars.iter().enumerate() {
-            if i % 4 == 0 && i > 0 {
-                writeln!(f, "|")?;
-            }
-            write!(f, "{}", bar)?;
-        }
-        writeln!(f, "|")?;
-        Ok(())
-    }
-}
-
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum WrittenElement {
     SectionMarker(String),
@@ -34,20 +22,55 @@ pub enum WrittenElement {
     RepeatTwoMeasures, // This is a special case because it takes 2 measures.
     Coda,
     Segno,
-    Comment(String),
+    Comment(Annotation, Position),
     AlternateChord(Chord),
     PauseSlash,
     Fermata,
+    /// The tune ends after this bar on the last time through.
+    EndingMeasure,
+}
+
+/// What's written in a cell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CellContent {
+    Chord(Chord, Width),
+    Slash,
+    RepeatMeasure,
+    RepeatTwoMeasures,
+}
+
+/// One of the 16 cells on a line of an iReal chart.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cell {
+    pub content: Option<CellContent>,
+    /// Drawn small, above the cell.
+    pub alternate: Option<Chord>,
+}
+
+impl Cell {
+    fn new(content: CellContent) -> Self {
+        Cell {
+            content: Some(content),
+            alternate: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WrittenBar {
-    repeat_start: bool,
-    repeat_end: bool,
-    double_start: bool,
-    double_end: bool,
-    final_bar: bool,
-    elements: Vec<WrittenElement>,
+    pub repeat_start: bool,
+    pub repeat_end: bool,
+    pub double_start: bool,
+    pub double_end: bool,
+    pub final_bar: bool,
+    /// Extra space above the line the bar is on.
+    pub vertical_space: u32,
+    /// Empty cells before the bar's opening barline, which line it up with
+    /// the bars above.
+    pub gap: usize,
+    /// The cells between the bar's barlines, in order.
+    pub cells: Vec<Cell>,
+    pub elements: Vec<WrittenElement>,
 }
 
 impl WrittenBar {
@@ -58,24 +81,35 @@ impl WrittenBar {
             double_start: false,
             double_end: false,
             final_bar: false,
+            vertical_space: 0,
+            gap: 0,
+            cells: vec![],
             elements: vec![],
         }
     }
 
+    /// A bar repeating the one before, written `Kcl`, which is `| x`.
     pub fn repeat() -> Self {
         WrittenBar {
-            repeat_start: false,
-            repeat_end: false,
-            double_start: false,
-            double_end: false,
-            final_bar: false,
+            cells: vec![Cell::default(), Cell::new(CellContent::RepeatMeasure)],
             elements: vec![WrittenElement::RepeatMeasure],
+            ..Self::new()
         }
     }
 
     pub fn is_empty(&self) -> bool {
         self.elements.is_empty() && !self.repeat_start && !self.repeat_end
     }
+
+    /* Spaces written between two bars, rather than in one, leave a gap
+     * before the next bar. */
+    fn into_gap(self) -> WrittenBar {
+        WrittenBar {
+            vertical_space: self.vertical_space,
+            gap: self.gap + self.cells.len(),
+            ..Self::new()
+        }
+    }
 }
 
 impl Default for WrittenBar {
@@ -84,93 +118,55 @@ impl Default for WrittenBar {
     }
 }
 
-impl fmt::Display for WrittenBar {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "|")?;
-        if self.double_start {
-            // TODO: If we ended the previous bar on a double bar, we should not write this.
-            write!(f, "|")?;
-        }
-        if self.repeat_start {
-            write!(f, ":")?;
-        }
-        write!(f, " ")?;
-        let mut count = 0;
-        for (i, element) in self.elements.iter().enumerate() {
-            if i > 0 {
-                write!(f, " ")?;
-            }
-            match element {
-                WrittenElement::SectionMarker(s) => write!(f, "[{}]", s)?,
-                WrittenElement::TimeSignature(ts) => write!(f, "{}", ts)?,
-                WrittenElement::Chord(c, width) => {
-                    let s = c.to_string();
-                    if *width == Width::Narrow {
-                        write!(f, "{:>6}", s)?;
-                        count += 1;
-                    } else {
-                        write!(f, "{:>6}      ", s)?;
-                        count += 2;
-                    }
-                }
-                WrittenElement::NumberedEnding(n) => write!(f, "N{}", n)?,
-                WrittenElement::RepeatMeasure => {
-                    write!(f, "            %           ")?;
-                    count += 4; // TODO: Time signature
-                }
-                WrittenElement::RepeatTwoMeasures => {
-                    write!(f, "                       %|")?;
-                    write!(f, "%                       ")?;
-                    count += 4; // TODO: Time signature
-                }
-                WrittenElement::Coda => {
-                    write!(f, "𝄌")?;
-                }
-                WrittenElement::Segno => {
-                    write!(f, "𝄋")?;
-                }
-                WrittenElement::Comment(s) => {
-                    write!(f, " ({})", s)?;
-                }
-                WrittenElement::AlternateChord(c) => {
-                    write!(f, " ({})", c)?;
-                }
-                WrittenElement::PauseSlash => {
-                    write!(f, "{:>6}", "/")?;
-                }
-                WrittenElement::Fermata => {
-                    write!(f, "𝄐")?;
-                }
-            }
-        }
-        // TODO: Take time signature into account
-        while count < 4 {
-            write!(f, "      ")?;
-            count += 1;
-        }
-        if self.repeat_end {
-            write!(f, ":")?;
-        }
-        if self.double_end {
-            write!(f, "|")?;
-        }
-        // Assume there's a | for the next line
-        Ok(())
-    }
+/// How music text is read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Skip text that can't be read, with a warning, rather than failing.
+    pub lenient: bool,
+}
+
+pub fn parse_music(text: &str) -> Result<Music, MusicError> {
+    let (music, _) = read_music(text.to_string(), &ParseOptions::default())?;
+    Ok(music)
 }
 
-pub fn parse_music(text: &str) -> Result<Music, String> {
-    // Remove blanks before parsing
-    println!("Text: {}", text);
-    let tokens = tokenize::tokenize(text)?;
-    println!("Tokens: {:?}", tokens);
+/* Read music text, with warnings for anything skipped. Takes the text so
+ * that it becomes `Music.raw` without being copied. */
+pub(crate) fn read_music(
+    text: String,
+    options: &ParseOptions,
+) -> Result<(Music, Vec<MusicError>), MusicError> {
+    let (lexemes, warnings) = if options.lenient {
+        tokenize::lex_lenient(&text)
+    } else {
+        (tokenize::lex(&text)?, vec![])
+    };
+    let tokens = lexemes
+        .iter()
+        .flat_map(|(_, token, second)| iter::once(token).chain(second));
+    let written_bars = read_tokens(tokens);
+    drop(lexemes);
+    Ok((
+        Music {
+            written_bars,
+            raw: text,
+        },
+        warnings,
+    ))
+}
 
+fn read_tokens<'a>(tokens: impl Iterator<Item = &'a Token<'a>>) -> Vec<WrittenBar> {
     let mut written_bars = vec![];
     let mut written_bar: WrittenBar = Default::default();
     let mut width = Width::Wide;
-    for token in tokens.iter() {
+    let mut pending_alternate = None;
+    for token in tokens {
+        let cells = written_bar.cells.len();
         match token {
             Token::RepeatStart => {
+                if written_bar.is_empty() {
+                    written_bar = written_bar.into_gap();
+                }
                 written_bar.repeat_start = true;
             }
             Token::RepeatEnd => {
@@ -181,7 +177,7 @@ pub fn parse_music(text: &str) -> Result<Music, String> {
             Token::SectionMarker(s) => {
                 written_bar
                     .elements
-                    .push(WrittenElement::SectionMarker(s.clone()));
+                    .push(WrittenElement::SectionMarker(s.to_string()));
             }
             Token::TimeSignature(top, bottom) => {
                 written_bar
@@ -192,18 +188,33 @@ pub fn parse_music(text: &str) -> Result<Music, String> {
                     }));
             }
             Token::Chord(c) => {
+                written_bar
+                    .cells
+                    .push(Cell::new(CellContent::Chord(c.clone(), width.clone())));
                 written_bar
                     .elements
                     .push(WrittenElement::Chord(c.clone(), width.clone()));
             }
-            Token::Comma | Token::Space | Token::Blank | Token::VerticalSpace => {
-                // Ignore these tokens
+            Token::VerticalSpace => {
+                written_bar.vertical_space += 1;
+            }
+            Token::Space => {
+                written_bar.cells.push(Cell::default());
+            }
+            Token::Blank => {
+                for _ in 0..3 {
+                    written_bar.cells.push(Cell::default());
+                }
             }
+            Token::Comma => {}
             Token::Bar => {
-                if !written_bar.is_empty() {
+                if written_bar.is_empty() {
+                    // Spacers and cells before a bar line belong to the next bar.
+                    written_bar = written_bar.into_gap();
+                } else {
                     written_bars.push(written_bar);
+                    written_bar = Default::default();
                 }
-                written_bar = Default::default();
             }
             Token::Squeeze => {
                 width = Width::Narrow;
@@ -217,6 +228,9 @@ pub fn parse_music(text: &str) -> Result<Music, String> {
                     .push(WrittenElement::NumberedEnding(*n));
             }
             Token::DoubleBarStart => {
+                if written_bar.is_empty() {
+                    written_bar = written_bar.into_gap();
+                }
                 written_bar.double_start = true;
             }
             Token::DoubleBarEnd => {
@@ -241,29 +255,49 @@ pub fn parse_music(text: &str) -> Result<Music, String> {
                 written_bar.elements.push(WrittenElement::Segno);
             }
             Token::Comment(s) => {
+                let (annotation, position) = Annotation::parse(s);
                 written_bar
                     .elements
-                    .push(WrittenElement::Comment(s.trim().into()));
+                    .push(WrittenElement::Comment(annotation, position));
             }
             Token::AlternateChord(c) => {
+                // Drawn over the cell before it, or the next one at the
+                // start of a bar.
+                match written_bar.cells.last_mut() {
+                    Some(cell) if cell.alternate.is_none() => cell.alternate = Some(c.clone()),
+                    _ => pending_alternate = Some(c.clone()),
+                }
                 written_bar
                     .elements
                     .push(WrittenElement::AlternateChord(c.clone()));
             }
             Token::RepeatMeasure => {
+                written_bar
+                    .cells
+                    .push(Cell::new(CellContent::RepeatMeasure));
                 written_bar.elements.push(WrittenElement::RepeatMeasure);
             }
             Token::RepeatTwoMeasures => {
+                written_bar
+                    .cells
+                    .push(Cell::new(CellContent::RepeatTwoMeasures));
                 written_bar.elements.push(WrittenElement::RepeatTwoMeasures);
             }
             Token::PauseSlash => {
+                written_bar.cells.push(Cell::new(CellContent::Slash));
                 written_bar.elements.push(WrittenElement::PauseSlash);
             }
             Token::Fermata => {
                 written_bar.elements.push(WrittenElement::Fermata);
             }
             Token::EndingMeasure => {
-                println!("Ending measure found, but not implemented yet.");
+                written_bar.elements.push(WrittenElement::EndingMeasure);
+            }
+            Token::Unknown(_) => {}
+        }
+        if written_bar.cells.len() > cells {
+            if let Some(alternate) = pending_alternate.take() {
+                written_bar.cells[cells].alternate = Some(alternate);
             }
         }
     }
@@ -271,8 +305,161 @@ pub fn parse_music(text: &str) -> Result<Music, String> {
         written_bars.push(written_bar);
     }
 
-    Ok(Music {
-        written_bars,
-        raw: text.to_string(),
-    })
+    written_bars
+}
+
+fn comment_text(annotation: &Annotation, position: &Position) -> String {
+    let mut text = String::from("<");
+    if let Some(offset) = position.offset {
+        text.push_str(&format!("*{:02}", offset));
+    }
+    text.push_str(&"XyQ".repeat(position.indent));
+    text.push_str(&annotation.to_string());
+    text.push('>');
+    text
+}
+
+/* Writes bars as music text, cell by cell, so that parsing the text gives
+ * the same bars back. */
+struct Writer {
+    text: String,
+    width: Width,
+    // Whether the text ends in a chord, which needs a comma before anything
+    // that could be read as part of it.
+    after_chord: bool,
+}
+
+impl Writer {
+    fn push(&mut self, token: &str) {
+        if self.after_chord {
+            self.text.push(',');
+        }
+        self.text.push_str(token);
+        self.after_chord = false;
+    }
+
+    fn spaces(&mut self, count: usize) {
+        self.text.push_str(&" ".repeat(count));
+        self.after_chord = false;
+    }
+
+    fn content(&mut self, content: &CellContent) {
+        match content {
+            CellContent::Chord(chord, width) => {
+                if *width != self.width {
+                    self.push(if *width == Width::Narrow { "s" } else { "l" });
+                    self.width = width.clone();
+                }
+                self.push(&Token::Chord(chord.clone()).to_string());
+                self.after_chord = true;
+            }
+            CellContent::Slash => self.push("p"),
+            CellContent::RepeatMeasure => self.push("x"),
+            CellContent::RepeatTwoMeasures => self.push("r|"),
+        }
+    }
+
+    fn bar(&mut self, bar: &WrittenBar, previous: Option<&WrittenBar>) {
+        self.spaces(bar.gap);
+        self.push(&"Y".repeat(bar.vertical_space as usize));
+        if bar.repeat_start {
+            self.push("{");
+        }
+        if bar.double_start {
+            self.push("[");
+        }
+        // Cells after a closing barline need an opening one.
+        let closed = previous.is_none_or(|p| p.repeat_end || p.double_end || p.final_bar);
+        if !bar.repeat_start && !bar.double_start && (bar.gap > 0 || (closed && previous.is_some()))
+        {
+            self.push("|");
+        }
+
+        let mut next = 0;
+        for element in &bar.elements {
+            match element {
+                WrittenElement::Chord(..)
+                | WrittenElement::PauseSlash
+                | WrittenElement::RepeatMeasure
+                | WrittenElement::RepeatTwoMeasures => {
+                    match bar.cells[next..].iter().position(|c| c.content.is_some()) {
+                        Some(empty) => {
+                            self.spaces(empty);
+                            next += empty;
+                            self.content(bar.cells[next].content.as_ref().unwrap());
+                            next += 1;
+                        }
+                        None => self.content(&match element {
+                            WrittenElement::Chord(chord, width) => {
+                                CellContent::Chord(chord.clone(), width.clone())
+                            }
+                            WrittenElement::PauseSlash => CellContent::Slash,
+                            WrittenElement::RepeatMeasure => CellContent::RepeatMeasure,
+                            _ => CellContent::RepeatTwoMeasures,
+                        }),
+                    }
+                }
+                WrittenElement::AlternateChord(chord) => {
+                    // Empty cells up to the one the chord is drawn over.
+                    let from = next.saturating_sub(1);
+                    if let Some(at) = bar.cells[from..]
+                        .iter()
+                        .position(|c| c.alternate.as_ref() == Some(chord))
+                    {
+                        let at = from + at;
+                        if at >= next {
+                            self.spaces(at + 1 - next);
+                            next = at + 1;
+                        }
+                    }
+                    self.text
+                        .push_str(&Token::AlternateChord(chord.clone()).to_string());
+                    self.after_chord = false;
+                }
+                WrittenElement::SectionMarker(s) => self.push(&format!("*{}", s)),
+                WrittenElement::TimeSignature(ts) => {
+                    self.push(&Token::TimeSignature(ts.top, ts.bottom).to_string())
+                }
+                WrittenElement::NumberedEnding(n) => self.push(&format!("N{}", n)),
+                WrittenElement::Coda => self.push("Q"),
+                WrittenElement::Segno => self.push("S"),
+                WrittenElement::Fermata => self.push("f"),
+                WrittenElement::EndingMeasure => self.push("U"),
+                WrittenElement::Comment(annotation, position) => {
+                    self.push(&comment_text(annotation, position))
+                }
+            }
+        }
+        self.spaces(bar.cells.len().saturating_sub(next));
+
+        self.text.push(if bar.repeat_end {
+            '}'
+        } else if bar.final_bar {
+            'Z'
+        } else if bar.double_end {
+            ']'
+        } else {
+            '|'
+        });
+        self.after_chord = false;
+    }
+}
+
+impl Music {
+    /// Music made from bars, with `raw` written to match. Parsing `raw`
+    /// gives the same bars back, with every chord in the same cell.
+    pub fn from_bars(written_bars: Vec<WrittenBar>) -> Music {
+        let mut writer = Writer {
+            text: String::new(),
+            width: Width::Wide,
+            after_chord: false,
+        };
+        for (i, bar) in written_bars.iter().enumerate() {
+            writer.bar(bar, i.checked_sub(1).map(|p| &written_bars[p]));
+        }
+        Music {
+            raw: writer.text,
+            written_bars,
+        }
+    }
 }
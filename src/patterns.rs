@@ -0,0 +1,221 @@
+use crate::{
+    analysis::Quality,
+    parse::Music,
+    timeline::TimedChord,
+    types::{Chord, Key},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatternKind {
+    /// ii-7 V7 I
+    MajorTwoFiveOne,
+    /// iiø7 V7 i
+    MinorTwoFiveOne,
+    /// ii-7 bII7 I, or bvi-7 bII7 I: the V replaced by its tritone
+    /// substitute.
+    TritoneTwoFiveOne,
+    /// I vi ii V, or iii vi ii V, in any mix of minor and dominant chords.
+    Turnaround,
+    /// iv-7 bVII7 I, or just bVII7 I.
+    Backdoor,
+    /// Three or more dominants in a row, each resolving down a fifth. Holds
+    /// the number of dominants.
+    DominantChain(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    /// Where the first chord starts, as an index into `Music.written_bars`
+    /// and a beat within that bar.
+    pub bar: usize,
+    pub beat: u32,
+    /// Where the last chord of the pattern starts.
+    pub end_bar: usize,
+    pub end_beat: u32,
+    /// The key the pattern resolves to, or points to if it doesn't resolve.
+    pub key: Key,
+    pub chords: Vec<Chord>,
+}
+
+/* A chord change. Consecutive chords with the same root and quality (bar
+ * repeats, C^7 to C6) count as one. */
+struct Change<'a> {
+    timed: &'a TimedChord,
+    root: u8,
+    quality: Quality,
+}
+
+fn changes(timeline: &[TimedChord]) -> Vec<Vec<Change<'_>>> {
+    // N.C. interrupts the harmony, so split the changes into phrases there.
+    let mut phrases = vec![];
+    let mut phrase: Vec<Change> = vec![];
+    for timed in timeline {
+        let (root, quality) = match (timed.chord.root_pitch_class(), timed.chord.quality()) {
+            (Some(root), Some(quality)) => (root, quality),
+            _ => {
+                phrases.push(std::mem::take(&mut phrase));
+                continue;
+            }
+        };
+        if let Some(previous) = phrase.last() {
+            if previous.root == root && previous.quality == quality {
+                continue;
+            }
+        }
+        phrase.push(Change {
+            timed,
+            root,
+            quality,
+        });
+    }
+    phrases.push(phrase);
+    phrases
+}
+
+fn interval(from: &Change, to: &Change) -> u8 {
+    (to.root + 12 - from.root) % 12
+}
+
+fn is_minor_like(change: &Change) -> bool {
+    change.quality == Quality::Minor
+}
+
+fn is_tonic(change: &Change) -> bool {
+    matches!(change.quality, Quality::Major | Quality::Minor)
+}
+
+fn pattern(kind: PatternKind, phrase: &[Change], key: Key) -> Pattern {
+    let first = phrase.first().unwrap().timed;
+    let last = phrase.last().unwrap().timed;
+    Pattern {
+        kind,
+        bar: first.bar,
+        beat: first.beat,
+        end_bar: last.bar,
+        end_beat: last.beat,
+        key,
+        chords: phrase.iter().map(|c| c.timed.chord.clone()).collect(),
+    }
+}
+
+fn find_in_phrase(phrase: &[Change], patterns: &mut Vec<Pattern>) {
+    for i in 0..phrase.len() {
+        let rest = &phrase[i..];
+
+        if let [two, five, one, ..] = rest {
+            if five.quality == Quality::Dominant && is_tonic(one) {
+                let resolves = interval(five, one) == 5;
+                let tritone = interval(five, one) == 11;
+                let ii_v = interval(two, five) == 5;
+                // D-7 Db7 C or Ab-7 Db7 C
+                let sub_ii_v = interval(two, one) == 10 || ii_v;
+                if resolves
+                    && ii_v
+                    && two.quality == Quality::Minor
+                    && one.quality == Quality::Major
+                {
+                    patterns.push(pattern(
+                        PatternKind::MajorTwoFiveOne,
+                        &rest[..3],
+                        Key::from_pitch_class(one.root, false),
+                    ));
+                } else if resolves
+                    && ii_v
+                    && matches!(two.quality, Quality::HalfDiminished | Quality::Minor)
+                    && one.quality == Quality::Minor
+                {
+                    patterns.push(pattern(
+                        PatternKind::MinorTwoFiveOne,
+                        &rest[..3],
+                        Key::from_pitch_class(one.root, true),
+                    ));
+                } else if tritone && sub_ii_v && is_minor_like(two) {
+                    patterns.push(pattern(
+                        PatternKind::TritoneTwoFiveOne,
+                        &rest[..3],
+                        Key::from_pitch_class(one.root, one.quality == Quality::Minor),
+                    ));
+                }
+            }
+        }
+
+        if let [first, six, two, five, ..] = rest {
+            let tonic = match (first.quality, interval(first, six)) {
+                (Quality::Major, 9) => Some(first.root),
+                // iii vi ii V
+                (Quality::Minor | Quality::Dominant, 5) => Some((first.root + 8) % 12),
+                _ => None,
+            };
+            if let Some(tonic) = tonic {
+                if matches!(six.quality, Quality::Minor | Quality::Dominant)
+                    && matches!(two.quality, Quality::Minor | Quality::Dominant)
+                    && five.quality == Quality::Dominant
+                    && interval(six, two) == 5
+                    && interval(two, five) == 5
+                {
+                    patterns.push(pattern(
+                        PatternKind::Turnaround,
+                        &rest[..4],
+                        Key::from_pitch_class(tonic, false),
+                    ));
+                }
+            }
+        }
+
+        if let [seven, one, ..] = rest {
+            if seven.quality == Quality::Dominant && interval(seven, one) == 2 && is_tonic(one) {
+                let start = match i.checked_sub(1).map(|j| &phrase[j]) {
+                    Some(four) if four.quality == Quality::Minor && interval(four, seven) == 5 => {
+                        i - 1
+                    }
+                    _ => i,
+                };
+                patterns.push(pattern(
+                    PatternKind::Backdoor,
+                    &phrase[start..i + 2],
+                    Key::from_pitch_class(one.root, one.quality == Quality::Minor),
+                ));
+            }
+        }
+
+        // Only report the longest chain, starting from its first dominant.
+        let chain_continues_from_before = i > 0
+            && phrase[i - 1].quality == Quality::Dominant
+            && interval(&phrase[i - 1], &phrase[i]) == 5;
+        if rest[0].quality == Quality::Dominant && !chain_continues_from_before {
+            let mut length = 1;
+            while length < rest.len()
+                && rest[length].quality == Quality::Dominant
+                && interval(&rest[length - 1], &rest[length]) == 5
+            {
+                length += 1;
+            }
+            if length >= 3 {
+                let target = (rest[length - 1].root + 5) % 12;
+                let minor = rest
+                    .get(length)
+                    .map(|next| next.root == target && next.quality == Quality::Minor)
+                    .unwrap_or(false);
+                patterns.push(pattern(
+                    PatternKind::DominantChain(length),
+                    &rest[..length],
+                    Key::from_pitch_class(target, minor),
+                ));
+            }
+        }
+    }
+}
+
+impl Music {
+    /// Find ii-V-Is, turnarounds, backdoor progressions and chains of
+    /// dominants, in written order. Patterns may overlap.
+    pub fn find_patterns(&self) -> Vec<Pattern> {
+        let timeline = self.timeline();
+        let mut patterns = vec![];
+        for phrase in changes(&timeline) {
+            find_in_phrase(&phrase, &mut patterns);
+        }
+        patterns
+    }
+}
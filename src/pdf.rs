@@ -0,0 +1,435 @@
+use std::{fmt::Write, io::Write as _};
+
+use crate::{
+    drawing::{text_width, Anchor, Drawing, Shape, PAGE_WIDTH},
+    svg::n,
+    transpose::Instrument,
+    Collection, Song,
+};
+
+/// US letter, in points.
+const PAGE_HEIGHT: f64 = 792.0;
+const MARGIN: f64 = 36.0;
+const TOC_LINE: f64 = 14.0;
+const TOC_TOP: f64 = 96.0;
+// Bezier control point distance for a quarter circle of radius 1.
+const KAPPA: f64 = 0.5523;
+
+/// How the table of contents, and the charts, are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TocOrder {
+    Title,
+    Composer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdfOptions {
+    /// Charts are transposed for this instrument.
+    pub instrument: Instrument,
+    pub order: TocOrder,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        PdfOptions {
+            instrument: Instrument::Concert,
+            order: TocOrder::Title,
+        }
+    }
+}
+
+// Fonts are the standard 14 PDF fonts, so nothing needs embedding.
+const FONTS: [&str; 3] = ["Helvetica", "Helvetica-Bold", "Helvetica-Oblique"];
+
+/* Text in the standard fonts is written in WinAnsiEncoding. Latin-1
+ * characters map straight across; music glyphs become their ASCII
+ * spellings. */
+fn encode_text(text: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => bytes.extend([b'\\', c as u8]),
+            ' '..='~' => bytes.push(c as u8),
+            '\u{a0}'..='\u{ff}' => bytes.push(c as u32 as u8),
+            '\u{266d}' => bytes.push(b'b'),
+            '\u{266f}' => bytes.push(b'#'),
+            '\u{2212}' | '\u{2013}' => bytes.push(0x96),
+            '\u{2014}' => bytes.push(0x97),
+            '\u{2018}' => bytes.push(0x91),
+            '\u{2019}' => bytes.push(0x92),
+            '\u{201c}' => bytes.push(0x93),
+            '\u{201d}' => bytes.push(0x94),
+            '\u{2026}' => bytes.push(0x85),
+            _ => bytes.push(b'?'),
+        }
+    }
+    bytes
+}
+
+/// The content stream for one page. Drawings taller than the page are
+/// scaled down to fit.
+fn page_content(drawing: &Drawing) -> Vec<u8> {
+    let scale = (PAGE_HEIGHT / drawing.height)
+        .min(PAGE_WIDTH / drawing.width)
+        .min(1.0);
+    let left = (PAGE_WIDTH - drawing.width * scale) / 2.0;
+    let x = |x: f64| n(left + x * scale);
+    let y = |y: f64| n(PAGE_HEIGHT - y * scale);
+
+    let mut content: Vec<u8> = vec![];
+    let mut ops = String::new();
+    for shape in &drawing.shapes {
+        match shape {
+            Shape::Line {
+                x1,
+                y1,
+                x2,
+                y2,
+                width,
+            } => writeln!(
+                ops,
+                "{} w {} {} m {} {} l S",
+                n(width * scale),
+                x(*x1),
+                y(*y1),
+                x(*x2),
+                y(*y2)
+            ),
+            Shape::Rect {
+                x: rx,
+                y: ry,
+                width,
+                height,
+            } => writeln!(
+                ops,
+                "1 w {} {} {} {} re S",
+                x(*rx),
+                y(ry + height),
+                n(width * scale),
+                n(height * scale)
+            ),
+            Shape::Circle {
+                x: cx,
+                y: cy,
+                radius,
+                filled,
+            } => {
+                // Four Bezier curves, one per quadrant.
+                let r = radius;
+                let k = r * KAPPA;
+                let point = |dx: f64, dy: f64| format!("{} {}", x(cx + dx), y(cy + dy));
+                writeln!(ops, "1 w {} m", point(*r, 0.0)).unwrap();
+                for (c1, c2, end) in [
+                    ((*r, k), (k, *r), (0.0, *r)),
+                    ((-k, *r), (-r, k), (-r, 0.0)),
+                    ((-r, -k), (-k, -r), (0.0, -r)),
+                    ((k, -r), (*r, -k), (*r, 0.0)),
+                ] {
+                    writeln!(
+                        ops,
+                        "{} {} {} c",
+                        point(c1.0, c1.1),
+                        point(c2.0, c2.1),
+                        point(end.0, end.1)
+                    )
+                    .unwrap();
+                }
+                writeln!(ops, "{}", if *filled { "f" } else { "S" })
+            }
+            Shape::Polyline { points, width } => {
+                write!(ops, "{} w", n(width * scale)).unwrap();
+                for (i, (px, py)) in points.iter().enumerate() {
+                    write!(
+                        ops,
+                        " {} {} {}",
+                        x(*px),
+                        y(*py),
+                        if i == 0 { "m" } else { "l" }
+                    )
+                    .unwrap();
+                }
+                writeln!(ops, " S")
+            }
+            Shape::Text {
+                x: tx,
+                y: ty,
+                size,
+                text,
+                anchor,
+                bold,
+                italic,
+            } => {
+                let font = match (bold, italic) {
+                    (true, _) => "F2",
+                    (false, true) => "F3",
+                    (false, false) => "F1",
+                };
+                let shift = match anchor {
+                    Anchor::Start => 0.0,
+                    Anchor::Middle => text_width(text, *size) / 2.0,
+                    Anchor::End => text_width(text, *size),
+                };
+                write!(
+                    ops,
+                    "BT /{} {} Tf {} {} Td (",
+                    font,
+                    n(size * scale),
+                    x(tx - shift),
+                    y(*ty)
+                )
+                .unwrap();
+                content.extend(ops.as_bytes());
+                ops.clear();
+                content.extend(encode_text(text));
+                writeln!(ops, ") Tj ET")
+            }
+        }
+        .unwrap();
+        content.extend(ops.as_bytes());
+        ops.clear();
+    }
+    content
+}
+
+/* Writes numbered objects and keeps their offsets for the cross-reference
+ * table. */
+struct PdfWriter {
+    bytes: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl PdfWriter {
+    fn new() -> Self {
+        PdfWriter {
+            bytes: b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec(),
+            offsets: vec![],
+        }
+    }
+
+    /// The number the next object will get.
+    fn next_id(&self) -> usize {
+        self.offsets.len() + 1
+    }
+
+    fn object(&mut self, body: &[u8]) -> usize {
+        self.offsets.push(self.bytes.len());
+        let id = self.offsets.len();
+        writeln!(self.bytes, "{} 0 obj", id).unwrap();
+        self.bytes.extend(body);
+        self.bytes.extend(b"\nendobj\n");
+        id
+    }
+
+    fn stream(&mut self, data: &[u8]) -> usize {
+        let mut body = format!("<< /Length {} >>\nstream\n", data.len()).into_bytes();
+        body.extend(data);
+        body.extend(b"\nendstream");
+        self.object(&body)
+    }
+
+    fn finish(mut self, root: usize, info: usize) -> Vec<u8> {
+        let xref = self.bytes.len();
+        write!(self.bytes, "xref\n0 {}\n", self.offsets.len() + 1).unwrap();
+        self.bytes.extend(b"0000000000 65535 f \n");
+        for offset in &self.offsets {
+            writeln!(self.bytes, "{:010} 00000 n ", offset).unwrap();
+        }
+        write!(
+            self.bytes,
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            root,
+            info,
+            xref
+        )
+        .unwrap();
+        self.bytes
+    }
+}
+
+fn page(shapes: Vec<Shape>) -> Drawing {
+    Drawing {
+        width: PAGE_WIDTH,
+        height: PAGE_HEIGHT,
+        shapes,
+    }
+}
+
+fn text(x: f64, y: f64, size: f64, text: &str, anchor: Anchor, bold: bool) -> Shape {
+    Shape::Text {
+        x,
+        y,
+        size,
+        text: text.to_string(),
+        anchor,
+        bold,
+        italic: false,
+    }
+}
+
+/// Shorten text to fit `width`, ending it with "...".
+fn fit(value: &str, size: f64, width: f64) -> String {
+    if text_width(value, size) <= width {
+        return value.to_string();
+    }
+    let mut chars: Vec<char> = value.chars().collect();
+    while !chars.is_empty() {
+        chars.pop();
+        let shortened: String = chars.iter().collect::<String>() + "...";
+        if text_width(&shortened, size) <= width {
+            return shortened;
+        }
+    }
+    String::new()
+}
+
+impl Collection {
+    /// A fake book of the whole collection as a PDF file: a title page, a
+    /// table of contents and one chart per page.
+    pub fn to_pdf(&self, options: &PdfOptions) -> Vec<u8> {
+        let mut songs: Vec<&Song> = self.songs.iter().collect();
+        let sort_key = |song: &Song| match options.order {
+            TocOrder::Title => (song.title.to_lowercase(), song.composer.to_lowercase()),
+            TocOrder::Composer => (song.composer.to_lowercase(), song.title.to_lowercase()),
+        };
+        songs.sort_by_key(|song| sort_key(song));
+        let part = options.instrument.label();
+
+        let mut pages: Vec<Drawing> = vec![];
+        let mut title_page = vec![text(
+            PAGE_WIDTH / 2.0,
+            PAGE_HEIGHT * 0.4,
+            36.0,
+            &self.title,
+            Anchor::Middle,
+            true,
+        )];
+        if let Some(part) = &part {
+            title_page.push(text(
+                PAGE_WIDTH / 2.0,
+                PAGE_HEIGHT * 0.4 + 40.0,
+                18.0,
+                part,
+                Anchor::Middle,
+                false,
+            ));
+        }
+        title_page.push(text(
+            PAGE_WIDTH / 2.0,
+            PAGE_HEIGHT * 0.4 + 70.0,
+            12.0,
+            &format!("{} songs", songs.len()),
+            Anchor::Middle,
+            false,
+        ));
+        pages.push(page(title_page));
+
+        let per_page = ((PAGE_HEIGHT - TOC_TOP - MARGIN) / TOC_LINE) as usize;
+        let toc_pages = songs.len().div_ceil(per_page).max(1);
+        let first_chart = 1 + toc_pages + 1;
+        let right = PAGE_WIDTH - MARGIN;
+        let mut chunks: Vec<&[&Song]> = songs.chunks(per_page).collect();
+        if chunks.is_empty() {
+            // An empty collection still gets a contents page.
+            chunks.push(&[]);
+        }
+        for (p, chunk) in chunks.into_iter().enumerate() {
+            let mut shapes = vec![];
+            if p == 0 {
+                shapes.push(text(MARGIN, 64.0, 22.0, "Contents", Anchor::Start, true));
+            }
+            for (i, song) in chunk.iter().enumerate() {
+                let y = TOC_TOP + i as f64 * TOC_LINE;
+                let number = first_chart + p * per_page + i;
+                let (first, second) = match options.order {
+                    TocOrder::Title => (&song.title, &song.composer),
+                    TocOrder::Composer => (&song.composer, &song.title),
+                };
+                shapes.push(text(
+                    MARGIN,
+                    y,
+                    10.0,
+                    &fit(first, 10.0, 260.0),
+                    Anchor::Start,
+                    false,
+                ));
+                shapes.push(text(
+                    MARGIN + 270.0,
+                    y,
+                    10.0,
+                    &fit(second, 10.0, 190.0),
+                    Anchor::Start,
+                    false,
+                ));
+                shapes.push(text(
+                    right,
+                    y,
+                    10.0,
+                    &number.to_string(),
+                    Anchor::End,
+                    false,
+                ));
+            }
+            pages.push(page(shapes));
+        }
+
+        for song in &songs {
+            pages.push(song.part(options.instrument).draw());
+        }
+        // Page numbers, going by the height of each page once it's scaled to
+        // fit the paper.
+        for (i, drawing) in pages.iter_mut().enumerate().skip(1) {
+            let height = drawing.height.max(PAGE_HEIGHT * drawing.width / PAGE_WIDTH);
+            drawing.shapes.push(text(
+                drawing.width / 2.0,
+                height - 18.0,
+                9.0,
+                &(i + 1).to_string(),
+                Anchor::Middle,
+                false,
+            ));
+        }
+
+        let mut pdf = PdfWriter::new();
+        let fonts: Vec<usize> = FONTS
+            .iter()
+            .map(|font| {
+                pdf.object(
+                    format!(
+                        "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+                        font
+                    )
+                    .as_bytes(),
+                )
+            })
+            .collect();
+        // Pages point at their parent, which is written after them.
+        let parent = pdf.next_id() + pages.len() * 2;
+        let mut kids = vec![];
+        for drawing in &pages {
+            let content = pdf.stream(&page_content(drawing));
+            kids.push(pdf.object(
+                format!(
+                    "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Contents {} 0 R /Resources << /Font << /F1 {} 0 R /F2 {} 0 R /F3 {} 0 R >> >> >>",
+                    parent, n(PAGE_WIDTH), n(PAGE_HEIGHT), content, fonts[0], fonts[1], fonts[2]
+                )
+                .as_bytes(),
+            ));
+        }
+        let kids: Vec<String> = kids.iter().map(|id| format!("{} 0 R", id)).collect();
+        let pages_id = pdf.object(
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids.join(" "),
+                kids.len()
+            )
+            .as_bytes(),
+        );
+        debug_assert_eq!(pages_id, parent);
+        let title: Vec<u8> = encode_text(&self.title);
+        let info = [b"<< /Title (".as_slice(), &title, b") >>"].concat();
+        let info = pdf.object(&info);
+        let root = pdf.object(format!("<< /Type /Catalog /Pages {} 0 R >>", pages_id).as_bytes());
+        pdf.finish(root, info)
+    }
+}
@@ -0,0 +1,255 @@
+use std::fmt;
+
+use crate::{
+    analysis::Quality,
+    parse::Music,
+    types::{AlteredNotes, Chord, Flavor, Key, Note, Number},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scale {
+    Ionian,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Aeolian,
+    Locrian,
+    LocrianSharp2,
+    MelodicMinor,
+    LydianDominant,
+    Altered,
+    PhrygianDominant,
+    HalfWholeDiminished,
+    WholeHalfDiminished,
+    WholeTone,
+}
+
+impl Scale {
+    /// Semitones above the root.
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            Scale::Ionian => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Scale::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Scale::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Scale::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Scale::Aeolian => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            Scale::LocrianSharp2 => &[0, 2, 3, 5, 6, 8, 10],
+            Scale::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            Scale::LydianDominant => &[0, 2, 4, 6, 7, 9, 10],
+            Scale::Altered => &[0, 1, 3, 4, 6, 8, 10],
+            Scale::PhrygianDominant => &[0, 1, 4, 5, 7, 8, 10],
+            Scale::HalfWholeDiminished => &[0, 1, 3, 4, 6, 7, 9, 10],
+            Scale::WholeHalfDiminished => &[0, 2, 3, 5, 6, 8, 9, 11],
+            Scale::WholeTone => &[0, 2, 4, 6, 8, 10],
+        }
+    }
+
+    /// The notes of the scale starting on `root`. Seven note scales use
+    /// each letter once where the notes allow it; other scales follow
+    /// `prefer_flats`.
+    pub fn notes(&self, root: &Note, prefer_flats: bool) -> Vec<Note> {
+        let root_pc = match root.pitch_class() {
+            Some(pc) => pc,
+            None => return vec![],
+        };
+        let intervals = self.intervals();
+        let letters = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+        let root_letter = letters
+            .iter()
+            .position(|&l| root.to_string().starts_with(l))
+            .unwrap_or(0);
+        intervals
+            .iter()
+            .enumerate()
+            .map(|(degree, interval)| {
+                let pc = (root_pc + interval) % 12;
+                if intervals.len() == 7 {
+                    let letter = letters[(root_letter + degree) % 7];
+                    for accidental in ["", "b", "#"] {
+                        if let Ok(note) = format!("{}{}", letter, accidental).parse::<Note>() {
+                            if note.pitch_class() == Some(pc) {
+                                return note;
+                            }
+                        }
+                    }
+                }
+                Note::from_pitch_class(pc, prefer_flats)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scale::Ionian => "Ionian",
+            Scale::Dorian => "Dorian",
+            Scale::Phrygian => "Phrygian",
+            Scale::Lydian => "Lydian",
+            Scale::Mixolydian => "Mixolydian",
+            Scale::Aeolian => "Aeolian",
+            Scale::Locrian => "Locrian",
+            Scale::LocrianSharp2 => "Locrian #2",
+            Scale::MelodicMinor => "Melodic minor",
+            Scale::LydianDominant => "Lydian dominant",
+            Scale::Altered => "Altered",
+            Scale::PhrygianDominant => "Phrygian dominant",
+            Scale::HalfWholeDiminished => "Half-whole diminished",
+            Scale::WholeHalfDiminished => "Whole-half diminished",
+            Scale::WholeTone => "Whole tone",
+        }
+        .fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScaleSuggestion {
+    pub scale: Scale,
+    pub root: Note,
+    pub notes: Vec<Note>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChordScales {
+    pub chord: Chord,
+    /// Index into `Music.written_bars`.
+    pub bar: usize,
+    pub beat: u32,
+    /// The key the chord was judged in.
+    pub key: Key,
+    /// Suggestions, best first.
+    pub scales: Vec<ScaleSuggestion>,
+}
+
+/// Scales that fit `chord` in `key`, best first. `next` is the chord that
+/// follows, which decides the scale for some dominants.
+pub fn scales_for_chord(chord: &Chord, key: &Key, next: Option<&Chord>) -> Vec<ScaleSuggestion> {
+    let (root, flavor, altered_notes) = match chord {
+        Chord::Some {
+            root,
+            flavor,
+            altered_notes,
+            ..
+        } => (root, flavor, altered_notes),
+        Chord::NC => return vec![],
+    };
+    let (quality, root_pc, tonic) =
+        match (chord.quality(), root.pitch_class(), key.tonic.pitch_class()) {
+            (Some(quality), Some(root_pc), Some(tonic)) => (quality, root_pc, tonic),
+            _ => return vec![],
+        };
+    let interval = (root_pc + 12 - tonic) % 12;
+    let has = |altered: AlteredNotes| altered_notes.contains(&altered);
+
+    use Scale::*;
+    let scales: Vec<Scale> = match quality {
+        Quality::Dominant => {
+            let resolution = next
+                .and_then(|next| Some((next.root_pitch_class()?, next.quality()?)))
+                .map(|(next_root, next_quality)| ((next_root + 12 - root_pc) % 12, next_quality));
+            if has(AlteredNotes::Alt) {
+                vec![Altered]
+            } else if has(AlteredNotes::Sharp(Number::Eleven)) {
+                vec![LydianDominant]
+            } else if matches!(flavor, Flavor::Augmented(_)) {
+                vec![WholeTone, Altered]
+            } else if has(AlteredNotes::Sus) {
+                vec![Mixolydian]
+            } else if has(AlteredNotes::Sharp(Number::Nine))
+                || has(AlteredNotes::Sharp(Number::Five))
+                || has(AlteredNotes::Flat(Number::Thirteen))
+            {
+                vec![Altered, PhrygianDominant]
+            } else if has(AlteredNotes::Flat(Number::Nine)) {
+                match resolution {
+                    Some((5, Quality::Minor)) => vec![PhrygianDominant, HalfWholeDiminished],
+                    _ => vec![HalfWholeDiminished, PhrygianDominant],
+                }
+            } else {
+                match resolution {
+                    // Resolving to a minor chord.
+                    Some((5, Quality::Minor | Quality::HalfDiminished)) => {
+                        vec![PhrygianDominant, Altered, Mixolydian]
+                    }
+                    // Tritone substitutes and backdoor dominants.
+                    Some((11, _)) => vec![LydianDominant, Mixolydian],
+                    _ if interval == 10 && !key.minor => vec![LydianDominant, Mixolydian],
+                    _ => vec![Mixolydian, LydianDominant],
+                }
+            }
+        }
+        Quality::Minor => match flavor {
+            Flavor::MinorMajor(_) => vec![MelodicMinor],
+            Flavor::Minor(Some(Number::Six)) | Flavor::MinorSixthNinth => {
+                vec![MelodicMinor, Dorian]
+            }
+            _ => match (key.minor, interval) {
+                (true, 0) => vec![Aeolian, Dorian, MelodicMinor],
+                (false, 4) => vec![Phrygian, Aeolian],
+                (false, 9) => vec![Aeolian, Dorian],
+                _ => vec![Dorian],
+            },
+        },
+        Quality::HalfDiminished => vec![LocrianSharp2, Locrian],
+        Quality::Diminished => vec![WholeHalfDiminished],
+        Quality::Augmented => vec![WholeTone],
+        Quality::Major => {
+            if has(AlteredNotes::Sharp(Number::Eleven)) {
+                vec![Lydian]
+            } else if (interval == 0 && !key.minor) || (interval == 3 && key.minor) {
+                vec![Ionian, Lydian]
+            } else {
+                vec![Lydian, Ionian]
+            }
+        }
+    };
+
+    let prefer_flats = root.is_flat() || key.prefers_flats();
+    scales
+        .into_iter()
+        .map(|scale| ScaleSuggestion {
+            scale,
+            root: root.clone(),
+            notes: scale.notes(root, prefer_flats),
+        })
+        .collect()
+}
+
+impl Music {
+    /// Scale suggestions for every chord, judged in the local key found by
+    /// `key_regions()`.
+    pub fn chord_scales(&self) -> Vec<ChordScales> {
+        let regions = self.key_regions();
+        let timeline: Vec<_> = self
+            .timeline()
+            .into_iter()
+            .filter(|timed| timed.chord.root_pitch_class().is_some())
+            .collect();
+        let mut region = 0;
+        timeline
+            .iter()
+            .enumerate()
+            .map(|(i, timed)| {
+                // Regions cover the same chords as the timeline, in order.
+                while region + 1 < regions.len()
+                    && (regions[region + 1].bar, regions[region + 1].beat)
+                        <= (timed.bar, timed.beat)
+                {
+                    region += 1;
+                }
+                let key = regions[region].key.clone();
+                let next = timeline.get(i + 1).map(|next| &next.chord);
+                ChordScales {
+                    chord: timed.chord.clone(),
+                    bar: timed.bar,
+                    beat: timed.beat,
+                    scales: scales_for_chord(&timed.chord, &key, next),
+                    key,
+                }
+            })
+            .collect()
+    }
+}
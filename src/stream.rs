@@ -0,0 +1,170 @@
+use std::io::{ErrorKind, Read};
+
+use crate::{decode_bytes, parse::ParseOptions, unescape_bytes, Song, SongError};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const PREFIX: &[u8] = b"irealb://";
+const SEPARATOR: &[u8] = b"===";
+
+/// Reads the songs of an irealb:// URL one at a time, so only the song
+/// being read is kept in memory. The collection's title comes last in the
+/// URL, so it's only known once every song has been read.
+pub struct SongIter<R> {
+    reader: R,
+    options: ParseOptions,
+    buffer: Box<[u8]>,
+    // URL text read but not yet unescaped: the start of the URL until the
+    // prefix is checked, then the end of the last read if it might be an
+    // escape cut in two or whitespace trimmed from the end of the URL.
+    raw: Vec<u8>,
+    prefix_checked: bool,
+    // Unescaped text not yet split into songs, searched as far as
+    // `searched` for a separator.
+    text: Vec<u8>,
+    searched: usize,
+    separated: bool,
+    eof: bool,
+    done: bool,
+    index: usize,
+    title: Option<String>,
+}
+
+impl<R: Read> SongIter<R> {
+    pub fn new(reader: R, options: &ParseOptions) -> SongIter<R> {
+        SongIter {
+            reader,
+            options: *options,
+            buffer: vec![0; CHUNK_SIZE].into_boxed_slice(),
+            raw: vec![],
+            prefix_checked: false,
+            text: vec![],
+            searched: 0,
+            separated: false,
+            eof: false,
+            done: false,
+            index: 0,
+            title: None,
+        }
+    }
+
+    /// The collection's title, once all the songs have been read.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    fn error(&self, message: String) -> SongError {
+        SongError {
+            index: self.index,
+            title: None,
+            message,
+            music: None,
+        }
+    }
+
+    /* Read and unescape some more of the URL. */
+    fn fill(&mut self) -> Result<(), String> {
+        let n = loop {
+            match self.reader.read(&mut self.buffer) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+        self.raw.extend_from_slice(&self.buffer[..n]);
+        self.eof = n == 0;
+
+        if !self.prefix_checked {
+            let start = self
+                .raw
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .unwrap_or(self.raw.len());
+            if self.raw.len() - start < PREFIX.len() && !self.eof {
+                return Ok(());
+            }
+            if !self.raw[start..].starts_with(PREFIX) {
+                return Err("Expected URL to start with 'irealb://'".to_string());
+            }
+            self.raw.drain(..start + PREFIX.len());
+            self.prefix_checked = true;
+        }
+
+        let trimmed = self.raw.len()
+            - self
+                .raw
+                .iter()
+                .rev()
+                .take_while(|b| b.is_ascii_whitespace())
+                .count();
+        let end = if self.eof {
+            trimmed
+        } else {
+            let tail = trimmed.saturating_sub(2);
+            match self.raw[tail..trimmed].iter().position(|&b| b == b'%') {
+                Some(i) => tail + i,
+                None => trimmed,
+            }
+        };
+        unescape_bytes(&self.raw[..end], &mut self.text)?;
+        if self.eof {
+            self.raw.clear();
+        } else {
+            self.raw.drain(..end);
+        }
+        Ok(())
+    }
+
+    fn song(&mut self, text: Vec<u8>) -> Result<Song, SongError> {
+        let index = self.index;
+        self.index += 1;
+        Song::from_text(index, &decode_bytes(text), &self.options)
+    }
+}
+
+impl<'a> SongIter<&'a [u8]> {
+    /// Songs from a URL already in memory.
+    pub fn from_text(text: &'a str, options: &ParseOptions) -> SongIter<&'a [u8]> {
+        SongIter::new(text.as_bytes(), options)
+    }
+}
+
+impl<R: Read> Iterator for SongIter<R> {
+    type Item = Result<Song, SongError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let found = self.text[self.searched..]
+                .windows(SEPARATOR.len())
+                .position(|w| w == SEPARATOR);
+            if let Some(i) = found {
+                let end = self.searched + i;
+                let rest = self.text.split_off(end + SEPARATOR.len());
+                let mut song = std::mem::replace(&mut self.text, rest);
+                song.truncate(end);
+                self.searched = 0;
+                self.separated = true;
+                return Some(self.song(song));
+            }
+            // A separator may start in the last bytes searched.
+            self.searched = self.text.len().saturating_sub(SEPARATOR.len() - 1);
+
+            if self.eof {
+                // What's left is the title, unless there was only one part,
+                // which is then a song.
+                self.done = true;
+                let rest = std::mem::take(&mut self.text);
+                if self.separated {
+                    self.title = Some(decode_bytes(rest));
+                    return None;
+                }
+                self.title = Some("No Title".to_string());
+                return Some(self.song(rest));
+            }
+            if let Err(message) = self.fill() {
+                self.done = true;
+                return Some(Err(self.error(message)));
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt::Write;
+
+use crate::{
+    drawing::{Anchor, Drawing, Shape},
+    parse::Music,
+    transpose::Part,
+    Song,
+};
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Two decimal places are plenty, and keep the output small. Also used for
+// numbers in PDF content streams.
+pub(crate) fn n(value: f64) -> String {
+    let text = format!("{:.2}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+impl Drawing {
+    /// Write the drawing as a standalone SVG document.
+    pub fn to_svg(&self) -> String {
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="Helvetica, Arial, sans-serif">"#,
+            w = n(self.width),
+            h = n(self.height)
+        )
+        .unwrap();
+        writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+        for shape in &self.shapes {
+            match shape {
+                Shape::Line {
+                    x1,
+                    y1,
+                    x2,
+                    y2,
+                    width,
+                } => writeln!(
+                    svg,
+                    r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="black" stroke-width="{}"/>"#,
+                    n(*x1),
+                    n(*y1),
+                    n(*x2),
+                    n(*y2),
+                    n(*width)
+                ),
+                Shape::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                } => writeln!(
+                    svg,
+                    r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="black"/>"#,
+                    n(*x),
+                    n(*y),
+                    n(*width),
+                    n(*height)
+                ),
+                Shape::Circle {
+                    x,
+                    y,
+                    radius,
+                    filled,
+                } => writeln!(
+                    svg,
+                    r#"<circle cx="{}" cy="{}" r="{}" {}/>"#,
+                    n(*x),
+                    n(*y),
+                    n(*radius),
+                    if *filled {
+                        r#"fill="black""#
+                    } else {
+                        r#"fill="none" stroke="black""#
+                    }
+                ),
+                Shape::Polyline { points, width } => {
+                    let points: Vec<String> = points
+                        .iter()
+                        .map(|(x, y)| format!("{},{}", n(*x), n(*y)))
+                        .collect();
+                    writeln!(
+                        svg,
+                        r#"<polyline points="{}" fill="none" stroke="black" stroke-width="{}"/>"#,
+                        points.join(" "),
+                        n(*width)
+                    )
+                }
+                Shape::Text {
+                    x,
+                    y,
+                    size,
+                    text,
+                    anchor,
+                    bold,
+                    italic,
+                } => {
+                    let mut attributes = String::new();
+                    match anchor {
+                        Anchor::Start => {}
+                        Anchor::Middle => attributes.push_str(r#" text-anchor="middle""#),
+                        Anchor::End => attributes.push_str(r#" text-anchor="end""#),
+                    }
+                    if *bold {
+                        attributes.push_str(r#" font-weight="bold""#);
+                    }
+                    if *italic {
+                        attributes.push_str(r#" font-style="italic""#);
+                    }
+                    writeln!(
+                        svg,
+                        r#"<text x="{}" y="{}" font-size="{}"{}>{}</text>"#,
+                        n(*x),
+                        n(*y),
+                        n(*size),
+                        attributes,
+                        escape(text)
+                    )
+                }
+            }
+            .unwrap();
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+impl Music {
+    /// The chart as an SVG image.
+    pub fn to_svg(&self) -> String {
+        self.draw().to_svg()
+    }
+}
+
+impl Song {
+    /// The chart with its header as an SVG image.
+    pub fn to_svg(&self) -> String {
+        self.draw().to_svg()
+    }
+}
+
+impl Part {
+    /// The part's chart as an SVG image.
+    pub fn to_svg(&self) -> String {
+        self.draw().to_svg()
+    }
+}
@@ -0,0 +1,103 @@
+use std::{fmt, ops::Range};
+
+use crate::tokenize::{self, Lexeme, MusicError, Token};
+
+/// A piece of music text as it was written, like `LZ`, `||`, `}|` or
+/// `XyQ`, and the tokens it stands for. Different spellings of the same
+/// tokens are told apart by their text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxNode {
+    /// Byte range of the piece in the music text.
+    pub span: Range<usize>,
+    pub tokens: Vec<Token<'static>>,
+}
+
+/// Music text split into the pieces it was written as, keeping every byte,
+/// so the text can be edited in place without writing the whole chart
+/// out again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxTree {
+    text: String,
+    nodes: Vec<SyntaxNode>,
+}
+
+fn node((span, token, second): Lexeme, offset: usize) -> SyntaxNode {
+    SyntaxNode {
+        span: span.start + offset..span.end + offset,
+        tokens: std::iter::once(token)
+            .chain(second)
+            .map(Token::into_owned)
+            .collect(),
+    }
+}
+
+fn nodes(text: &str, offset: usize) -> Result<Vec<SyntaxNode>, MusicError> {
+    Ok(tokenize::lex(text)?
+        .into_iter()
+        .map(|lexeme| node(lexeme, offset))
+        .collect())
+}
+
+impl SyntaxTree {
+    pub fn parse(text: &str) -> Result<SyntaxTree, MusicError> {
+        Ok(SyntaxTree {
+            text: text.to_string(),
+            nodes: nodes(text, 0)?,
+        })
+    }
+
+    /// The tree for any music text, with text that can't be read kept as
+    /// `Token::Unknown`.
+    pub fn parse_lenient(text: &str) -> SyntaxTree {
+        let (lexemes, _) = tokenize::lex_lenient(text);
+        SyntaxTree {
+            text: text.to_string(),
+            nodes: lexemes.into_iter().map(|lexeme| node(lexeme, 0)).collect(),
+        }
+    }
+
+    /// The music text, exactly as parsed or edited.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn nodes(&self) -> &[SyntaxNode] {
+        &self.nodes
+    }
+
+    /// The text a node was written as.
+    pub fn node_text(&self, node: &SyntaxNode) -> &str {
+        &self.text[node.span.clone()]
+    }
+
+    pub fn tokens(&self) -> Vec<Token<'static>> {
+        self.nodes
+            .iter()
+            .flat_map(|node| node.tokens.iter().cloned())
+            .collect()
+    }
+
+    /// Replace the node at `index` with new text, which may be empty or
+    /// several pieces long. The rest of the text is left as it was.
+    pub fn replace(&mut self, index: usize, text: &str) -> Result<(), String> {
+        let span = match self.nodes.get(index) {
+            Some(node) => node.span.clone(),
+            None => return Err(format!("No node {} in the music", index)),
+        };
+        let new_nodes = nodes(text, span.start)?;
+        let shift = text.len() as isize - span.len() as isize;
+        for node in &mut self.nodes[index + 1..] {
+            node.span = (node.span.start as isize + shift) as usize
+                ..(node.span.end as isize + shift) as usize;
+        }
+        self.text.replace_range(span, text);
+        self.nodes.splice(index..index + 1, new_nodes);
+        Ok(())
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt;
+
+use crate::{
+    analysis::Quality,
+    layout::{lay_out_bar, BarLayout, Barline, Row, CELLS_PER_BAR},
+    parse::{CellContent, Music, WrittenBar},
+    types::{Chord, TimeSignature},
+};
+
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TerminalOptions {
+    /// Colour section markers and chords by quality with ANSI escapes.
+    pub colour: bool,
+    /// Characters per beat. A bar of 4/4 is four beats wide.
+    pub beat_width: usize,
+}
+
+impl Default for TerminalOptions {
+    fn default() -> Self {
+        TerminalOptions {
+            colour: false,
+            beat_width: 4,
+        }
+    }
+}
+
+/* Text placed at a column of a line. Items that would overlap the one
+ * before them are pushed right rather than dropped. */
+struct Item {
+    column: usize,
+    text: String,
+    colour: Option<&'static str>,
+    // Whether to keep a space from the item before when pushed right.
+    spaced: bool,
+}
+
+fn render_line(mut items: Vec<Item>, colour: bool) -> String {
+    items.sort_by_key(|item| item.column);
+    let mut line = String::new();
+    let mut cursor = 0;
+    for item in items {
+        if item.column > cursor {
+            line.push_str(&" ".repeat(item.column - cursor));
+            cursor = item.column;
+        } else if item.spaced && cursor > 0 && !line.ends_with(' ') {
+            line.push(' ');
+            cursor += 1;
+        }
+        match item.colour {
+            Some(code) if colour => {
+                line.push_str(code);
+                line.push_str(&item.text);
+                line.push_str(RESET);
+            }
+            _ => line.push_str(&item.text),
+        }
+        cursor += item.text.chars().count();
+    }
+    line.trim_end().to_string()
+}
+
+fn barline_text(barline: Barline) -> &'static str {
+    match barline {
+        Barline::Single => "| ",
+        Barline::Double => "||",
+        Barline::Final => "|]",
+        Barline::RepeatStart => "|:",
+        Barline::RepeatEnd => ":|",
+        Barline::RepeatBoth => "::",
+    }
+}
+
+fn end_text(barline: Barline) -> String {
+    match barline {
+        Barline::Single => "|".to_string(),
+        barline => barline_text(barline).to_string(),
+    }
+}
+
+fn chord_colour(chord: &Chord) -> Option<&'static str> {
+    Some(match chord.quality()? {
+        Quality::Major => "\x1b[34m",
+        Quality::Minor => "\x1b[32m",
+        Quality::Dominant => "\x1b[33m",
+        Quality::HalfDiminished | Quality::Diminished => "\x1b[35m",
+        Quality::Augmented => "\x1b[31m",
+    })
+}
+
+fn item(column: usize, text: String, colour: Option<&'static str>, spaced: bool) -> Item {
+    Item {
+        column,
+        text,
+        colour,
+        spaced,
+    }
+}
+
+/// Columns taken by each bar in the row, in the row's time signatures.
+fn bar_widths(
+    bars: &[BarLayout],
+    time_signature: &mut TimeSignature,
+    beat_width: usize,
+) -> Vec<usize> {
+    bars.iter()
+        .map(|bar| {
+            if let Some(ts) = &bar.time_signature {
+                *time_signature = ts.clone();
+            }
+            let measures = (bar.cells.len() / CELLS_PER_BAR).max(1);
+            measures * time_signature.top as usize * beat_width
+        })
+        .collect()
+}
+
+/* The lines of a row: markers, ending brackets, the bars, and comments
+ * below. Markers get their own line so they can't run into the brackets. */
+fn render_row(
+    row: &Row,
+    time_signature: &mut TimeSignature,
+    options: &TerminalOptions,
+) -> [String; 4] {
+    let widths = bar_widths(&row.bars, time_signature, options.beat_width);
+    let (mut above, mut endings, mut bars, mut below) = (vec![], vec![], vec![], vec![]);
+    let mut column = 0;
+    for (i, (bar, width)) in row.bars.iter().zip(&widths).enumerate() {
+        // Leave a beat's width for each empty cell before the bar.
+        let gap = match i.checked_sub(1).map(|p| &row.bars[p]) {
+            Some(previous) => {
+                let gap = bar.first_cell - (previous.first_cell + previous.cells.len());
+                if gap > 0 {
+                    bars.push(item(column, end_text(previous.end), None, true));
+                    column += 2;
+                }
+                gap
+            }
+            None => bar.first_cell,
+        };
+        column += gap * options.beat_width;
+        bars.push(item(
+            column,
+            barline_text(bar.start).to_string(),
+            None,
+            true,
+        ));
+        let start = column + 2;
+
+        if let Some(n) = bar.ending {
+            let span: usize = widths[i..]
+                .iter()
+                .take(bar.ending_bars)
+                .map(|w| w + 2)
+                .sum();
+            let text = format!("{}.{}", n, "_".repeat(span.saturating_sub(4)));
+            endings.push(item(column, text, None, false));
+        }
+        let mut markers = vec![];
+        if let Some(section) = &bar.section {
+            markers.push((format!("[{}]", section), Some("\x1b[1;7m")));
+        }
+        if let Some(ts) = &bar.time_signature {
+            markers.push((ts.to_string(), None));
+        }
+        if bar.segno {
+            markers.push(("\u{1d10b}".to_string(), Some("\x1b[1m")));
+        }
+        if bar.coda {
+            markers.push(("\u{1d10c}".to_string(), Some("\x1b[1m")));
+        }
+        if bar.fermata {
+            markers.push(("\u{1d110}".to_string(), None));
+        }
+        if bar.ending_measure {
+            markers.push(("END".to_string(), Some("\x1b[1m")));
+        }
+        // Markers go over the start of the bar.
+        let mut marker_column = column;
+        for (text, colour) in markers {
+            let length = text.chars().count();
+            above.push(item(marker_column, text, colour, true));
+            marker_column += length + 1;
+        }
+
+        let cells = bar.cells.len().max(1);
+        for (i, cell) in bar.cells.iter().enumerate() {
+            let cell_column = start + i * width / cells;
+            match &cell.content {
+                Some(CellContent::Chord(chord, _)) => {
+                    let text = match chord {
+                        Chord::NC => "N.C.".to_string(),
+                        chord => chord.to_string(),
+                    };
+                    bars.push(item(cell_column, text, chord_colour(chord), true));
+                }
+                Some(CellContent::Slash) => {
+                    bars.push(item(cell_column, "/".to_string(), None, true))
+                }
+                // Repeat signs are drawn across the whole bar.
+                _ => {}
+            }
+            if let Some(alternate) = &cell.alternate {
+                above.push(item(cell_column, format!("({})", alternate), None, true));
+            }
+        }
+        match bar.simile {
+            1 => bars.push(item(start + width / 2 - 1, "%".to_string(), None, true)),
+            2 => bars.push(item(start + width / 2 - 1, "%2".to_string(), None, true)),
+            _ => {}
+        }
+        if !bar.comments.is_empty() {
+            let comments: Vec<String> = bar.comments.iter().map(|c| c.to_string()).collect();
+            below.push(item(start, comments.join("; "), Some("\x1b[2m"), true));
+        }
+        column = start + width;
+    }
+    if let Some(last) = row.bars.last() {
+        bars.push(item(column, end_text(last.end), None, true));
+    }
+    [
+        render_line(above, options.colour),
+        render_line(endings, options.colour),
+        render_line(bars, options.colour),
+        render_line(below, options.colour),
+    ]
+}
+
+impl Music {
+    /// The chart as text for a terminal, four bars to a row like iReal,
+    /// with each bar as wide as its number of beats.
+    pub fn to_terminal(&self, options: &TerminalOptions) -> String {
+        let mut text = String::new();
+        let mut time_signature = TimeSignature { top: 4, bottom: 4 };
+        for row in &self.layout().rows {
+            text.push_str(&"\n".repeat(row.space as usize));
+            for line in render_row(row, &mut time_signature, options) {
+                if !line.is_empty() {
+                    text.push_str(&line);
+                    text.push('\n');
+                }
+            }
+        }
+        text
+    }
+}
+
+impl fmt::Display for Music {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_terminal(&TerminalOptions::default()))
+    }
+}
+
+impl fmt::Display for WrittenBar {
+    /// The bar on its own, in 4/4 unless it changes the time signature.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let row = Row {
+            space: 0,
+            bars: vec![lay_out_bar(0, self)],
+        };
+        let mut time_signature = TimeSignature { top: 4, bottom: 4 };
+        let [_, _, bars, _] = render_row(&row, &mut time_signature, &TerminalOptions::default());
+        write!(f, "{}", bars)
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    annotation::Annotation,
+    parse::{Cell, CellContent, Music, WrittenElement},
+    types::{Chord, TimeSignature},
+};
+
+/// A chord placed in time. Chords are listed in written order; repeats and
+/// endings are not expanded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedChord {
+    pub chord: Chord,
+    /// Index into `Music.written_bars`.
+    pub bar: usize,
+    /// Beat within the bar where the chord starts, counting from 0. A bar
+    /// holding a two measure repeat covers two measures' worth of beats.
+    pub beat: u32,
+    /// How many beats the chord lasts. A chord lasts until the next chord,
+    /// so this can extend past the end of its bar.
+    pub beats: u32,
+    pub time_signature: TimeSignature,
+}
+
+/* The beats each chord in a bar lasts, from the cells they're written in,
+ * if the cells split the bar into whole beats. */
+fn cell_beats(cells: &[Cell], beats_per_bar: u32) -> Option<Vec<u32>> {
+    let starts: Vec<usize> = cells
+        .iter()
+        .enumerate()
+        .filter(|(_, cell)| matches!(cell.content, Some(CellContent::Chord(..))))
+        .map(|(i, _)| i)
+        .collect();
+    if starts.first() != Some(&0) {
+        return None;
+    }
+    let total = cells.len() as u32;
+    starts
+        .iter()
+        .zip(starts.iter().skip(1).chain([&cells.len()]))
+        .map(|(start, end)| {
+            let beats = (end - start) as u32 * beats_per_bar;
+            beats.is_multiple_of(total).then_some(beats / total)
+        })
+        .collect()
+}
+
+impl Music {
+    /// Every chord in the music, in written order, with its position and
+    /// duration. Bar repeats are filled in with the chords they repeat.
+    ///
+    /// iReal doesn't store durations. Where the bar's cells divide evenly
+    /// into beats, a chord lasts until the cell of the next one; otherwise
+    /// beats are split evenly between the chords in a bar, with any
+    /// remainder going to the first chords.
+    pub fn timeline(&self) -> Vec<TimedChord> {
+        let mut timeline: Vec<TimedChord> = vec![];
+        let mut time_signature = TimeSignature { top: 4, bottom: 4 };
+        // The chords of each measure so far, for bar repeats.
+        let mut measures: Vec<Vec<Chord>> = vec![];
+
+        for (bar_index, bar) in self.written_bars.iter().enumerate() {
+            for element in &bar.elements {
+                if let WrittenElement::TimeSignature(ts) = element {
+                    time_signature = ts.clone();
+                }
+            }
+
+            let mut bar_measures: Vec<Vec<Chord>> = vec![];
+            let chords: Vec<Chord> = bar
+                .elements
+                .iter()
+                .filter_map(|element| match element {
+                    WrittenElement::Chord(chord, _) => Some(chord.clone()),
+                    _ => None,
+                })
+                .collect();
+            if !chords.is_empty() {
+                bar_measures.push(chords);
+            } else if bar.elements.contains(&WrittenElement::RepeatMeasure) {
+                bar_measures.push(measures.last().cloned().unwrap_or_default());
+            } else if bar.elements.contains(&WrittenElement::RepeatTwoMeasures) {
+                let start = measures.len().saturating_sub(2);
+                bar_measures.extend(measures[start..].iter().cloned());
+            } else {
+                bar_measures.push(vec![]);
+            }
+
+            let positioned = cell_beats(&bar.cells, time_signature.top);
+            for (i, chords) in bar_measures.iter().enumerate() {
+                let offset = i as u32 * time_signature.top;
+                if chords.is_empty() {
+                    // The previous chord carries on through this measure.
+                    if let Some(last) = timeline.last_mut() {
+                        last.beats += time_signature.top;
+                    }
+                    continue;
+                }
+                let count = chords.len() as u32;
+                let mut beat = 0;
+                for (j, chord) in chords.iter().enumerate() {
+                    let mut beats = time_signature.top / count;
+                    if (j as u32) < time_signature.top % count {
+                        beats += 1;
+                    }
+                    if let Some(positioned) =
+                        positioned.as_ref().filter(|p| p.len() == chords.len())
+                    {
+                        beats = positioned[j];
+                    }
+                    timeline.push(TimedChord {
+                        chord: chord.clone(),
+                        bar: bar_index,
+                        beat: offset + beat,
+                        beats: beats.max(1),
+                        time_signature: time_signature.clone(),
+                    });
+                    beat += beats;
+                }
+            }
+            measures.extend(bar_measures);
+        }
+        timeline
+    }
+}
+
+impl Music {
+    /// Indices into `written_bars` in the order they're played, following
+    /// repeats and numbered endings. A repeat is played as many times as a
+    /// repeat count like `<3x>` on its closing bar says.
+    ///
+    /// D.C., D.S. and Fine directions are deliberately not followed: charts
+    /// often mean them only after solos or on cue, which is up to the
+    /// player. They can be found with `Annotation`.
+    pub fn playback_order(&self) -> Vec<usize> {
+        let bars = &self.written_bars;
+        let ending = |i: usize| {
+            bars[i].elements.iter().find_map(|element| match element {
+                WrittenElement::NumberedEnding(n) => Some(*n),
+                _ => None,
+            })
+        };
+        let repeat_count = |i: usize| {
+            bars[i].elements.iter().find_map(|element| match element {
+                WrittenElement::Comment(Annotation::RepeatCount { count, .. }, _) => Some(*count),
+                _ => None,
+            })
+        };
+        // How many times the repeat starting at `start` is played: once for
+        // each numbered ending, or as its repeat count says, or twice.
+        let passes = |start: usize| {
+            let mut most = 0;
+            let mut count = None;
+            let mut seen_end = false;
+            for (i, bar) in bars.iter().enumerate().skip(start) {
+                if i > start && bar.repeat_start {
+                    break;
+                }
+                match ending(i) {
+                    Some(n) => most = most.max(n),
+                    None if seen_end => break,
+                    None => {}
+                }
+                if bar.repeat_end {
+                    count = count.or(repeat_count(i));
+                    seen_end = true;
+                }
+            }
+            most.max(count.unwrap_or(2))
+        };
+
+        let mut order = vec![];
+        let mut repeat_start = 0;
+        let mut pass = 1;
+        let mut total = passes(0);
+        let mut i = 0;
+        // Malformed repeats shouldn't loop forever.
+        while i < bars.len() && order.len() < bars.len() * 10 {
+            // Coming back to the start of a repeat isn't a new repeat.
+            if bars[i].repeat_start && (i != repeat_start || pass == 1) {
+                repeat_start = i;
+                pass = 1;
+                total = passes(i);
+            }
+            if let Some(n) = ending(i) {
+                if n < pass {
+                    // Skip to this pass's ending.
+                    match (i + 1..bars.len()).find(|&j| ending(j) == Some(pass)) {
+                        Some(j) => {
+                            if pass == total {
+                                // The last ending is played straight through.
+                                repeat_start = j;
+                                pass = 1;
+                                total = 2;
+                            }
+                            i = j;
+                        }
+                        None => i += 1,
+                    }
+                    continue;
+                }
+            }
+            order.push(i);
+            if bars[i].repeat_end {
+                if pass < total {
+                    pass += 1;
+                    i = repeat_start;
+                    continue;
+                }
+                repeat_start = i + 1;
+                pass = 1;
+                total = 2;
+            }
+            i += 1;
+        }
+        order
+    }
+
+    /// The order bars are played the last time through the tune: like
+    /// `playback_order`, but stopping after the ending measure, so any
+    /// turnaround after it is left out.
+    pub fn last_time_order(&self) -> Vec<usize> {
+        let mut order = self.playback_order();
+        let ends = |i: &usize| {
+            self.written_bars[*i]
+                .elements
+                .contains(&WrittenElement::EndingMeasure)
+        };
+        if let Some(last) = order.iter().rposition(ends) {
+            order.truncate(last + 1);
+        }
+        order
+    }
+}
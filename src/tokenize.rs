@@ -7,9 +7,17 @@ use nom::bytes::complete::take_until;
 use nom::character::complete::digit1;
 use nom::combinator::all_consuming;
 use nom::combinator::map;
+use nom::combinator::map_res;
+use nom::combinator::opt;
+use nom::error::ErrorKind;
+use nom::multi::fold_many0;
 use nom::multi::many0;
+use nom::multi::separated_list1;
 use nom::sequence::tuple;
 use nom::IResult;
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
 
 use crate::types::AlteredNotes;
 use crate::types::Chord;
@@ -23,14 +31,16 @@ pub enum Width {
     Narrow,
 }
 
+/// A piece of iReal music. Text in comments and the like is borrowed from
+/// the music text where possible.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub enum Token {
+pub enum Token<'a> {
     AlternateChord(Chord), // These show up above the regular music.
     Bar,
     Blank,
     Chord(Chord),
     Coda,
-    Comment(String), // There is some stuff inside the comment that we could probably parse.
+    Comment(Cow<'a, str>), // Read into an `Annotation` by the parser.
     DoubleBarEnd,
     DoubleBarStart,
     EndingMeasure,
@@ -42,7 +52,7 @@ pub enum Token {
     BarAndRepeat,
     RepeatTwoMeasures,
     RepeatStart,
-    SectionMarker(String),
+    SectionMarker(Cow<'a, str>),
     Segno,
     TimeSignature(u32, u32),
     VerticalSpace,
@@ -51,28 +61,104 @@ pub enum Token {
     Squeeze,
     Unsqueeze,
 
-    // I think this is like in BiaB. Comma is to put two chords in the first
-    // half of a measure, and space is just to have one chord in each half.
+    // Every chord, space and slash takes one of the 16 cells on a line, and
+    // a blank takes three. Commas separate chords without taking a cell.
     Comma,
     Space,
+
+    // Text that couldn't be read, kept when parsing leniently.
+    Unknown(Cow<'a, str>),
+}
+
+impl fmt::Display for Token<'_> {
+    /// The token as iReal music text. Variants that tokenize the same, like
+    /// `|` and `||`, are all written the same way.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::AlternateChord(chord) => write!(f, "({})", chord),
+            Token::Bar => write!(f, "|"),
+            Token::Blank => write!(f, "XyQ"),
+            Token::Chord(Chord::NC) => write!(f, "n"),
+            Token::Chord(chord) => write!(f, "{}", chord),
+            Token::Coda => write!(f, "Q"),
+            Token::Comment(s) => write!(f, "<{}>", s),
+            Token::DoubleBarEnd => write!(f, "]"),
+            Token::DoubleBarStart => write!(f, "["),
+            Token::EndingMeasure => write!(f, "U"),
+            Token::FinalBar => write!(f, "Z"),
+            Token::NumberedEnding(n) => write!(f, "N{}", n),
+            Token::PauseSlash => write!(f, "p"),
+            Token::RepeatEnd => write!(f, "}}"),
+            Token::RepeatMeasure => write!(f, "x"),
+            Token::BarAndRepeat => write!(f, "Kcl"),
+            Token::RepeatTwoMeasures => write!(f, "r|"),
+            Token::RepeatStart => write!(f, "{{"),
+            Token::SectionMarker(s) => write!(f, "*{}", s),
+            Token::Segno => write!(f, "S"),
+            Token::TimeSignature(12, 8) => write!(f, "T12"),
+            Token::TimeSignature(top, bottom) => write!(f, "T{}{}", top, bottom),
+            Token::VerticalSpace => write!(f, "Y"),
+            Token::Fermata => write!(f, "f"),
+            Token::Squeeze => write!(f, "s"),
+            Token::Unsqueeze => write!(f, "l"),
+            Token::Comma => write!(f, ","),
+            Token::Space => write!(f, " "),
+            Token::Unknown(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Token<'_> {
+    /// The token with its own copy of any text.
+    pub fn into_owned(self) -> Token<'static> {
+        match self {
+            Token::Comment(s) => Token::Comment(Cow::Owned(s.into_owned())),
+            Token::SectionMarker(s) => Token::SectionMarker(Cow::Owned(s.into_owned())),
+            Token::Unknown(s) => Token::Unknown(Cow::Owned(s.into_owned())),
+            Token::AlternateChord(chord) => Token::AlternateChord(chord),
+            Token::Bar => Token::Bar,
+            Token::Blank => Token::Blank,
+            Token::Chord(chord) => Token::Chord(chord),
+            Token::Coda => Token::Coda,
+            Token::DoubleBarEnd => Token::DoubleBarEnd,
+            Token::DoubleBarStart => Token::DoubleBarStart,
+            Token::EndingMeasure => Token::EndingMeasure,
+            Token::FinalBar => Token::FinalBar,
+            Token::NumberedEnding(n) => Token::NumberedEnding(n),
+            Token::PauseSlash => Token::PauseSlash,
+            Token::RepeatEnd => Token::RepeatEnd,
+            Token::RepeatMeasure => Token::RepeatMeasure,
+            Token::BarAndRepeat => Token::BarAndRepeat,
+            Token::RepeatTwoMeasures => Token::RepeatTwoMeasures,
+            Token::RepeatStart => Token::RepeatStart,
+            Token::Segno => Token::Segno,
+            Token::TimeSignature(top, bottom) => Token::TimeSignature(top, bottom),
+            Token::VerticalSpace => Token::VerticalSpace,
+            Token::Fermata => Token::Fermata,
+            Token::Squeeze => Token::Squeeze,
+            Token::Unsqueeze => Token::Unsqueeze,
+            Token::Comma => Token::Comma,
+            Token::Space => Token::Space,
+        }
+    }
 }
 
 fn section_marker<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token> {
-    map(tuple((tag("*"), take(1usize))), |x: (&str, &str)| {
-        Token::SectionMarker(x.1.to_string())
+    map(tuple((tag("*"), take(1usize))), |x: (&'a str, &'a str)| {
+        Token::SectionMarker(Cow::Borrowed(x.1))
     })
 }
 
 fn numbered_ending<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token> {
-    map(tuple((tag("N"), take(1usize))), |x: (&str, &str)| {
-        Token::NumberedEnding(x.1.parse().unwrap())
+    map_res(tuple((tag("N"), take(1usize))), |x: (&str, &str)| {
+        x.1.parse().map(Token::NumberedEnding)
     })
 }
 
 fn comment<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token> {
     map(
         tuple((tag("<"), take_until(">"), tag(">"))),
-        |x: (&str, &str, &str)| Token::Comment(x.1.to_string()),
+        |x: (&'a str, &'a str, &'a str)| Token::Comment(Cow::Borrowed(x.1)),
     )
 }
 
@@ -83,109 +169,273 @@ fn alternate<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token> {
     )
 }
 
+fn tag_error(input: &str) -> nom::Err<nom::error::Error<&str>> {
+    nom::Err::Error(nom::error::Error::new(input, ErrorKind::Tag))
+}
+
 fn note<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Note> {
-    // Put longer strings first.
+    |input: &'a str| {
+        // The natural, flat and sharp notes for each letter.
+        let (natural, flat, sharp) = match input.as_bytes().first() {
+            Some(b'A') => (Note::A, Some(Note::AFlat), Some(Note::ASharp)),
+            Some(b'B') => (Note::B, Some(Note::BFlat), None),
+            Some(b'C') => (Note::C, Some(Note::CFlat), Some(Note::CSharp)),
+            Some(b'D') => (Note::D, Some(Note::DFlat), Some(Note::DSharp)),
+            Some(b'E') => (Note::E, Some(Note::EFlat), None),
+            Some(b'F') => (Note::F, None, Some(Note::FSharp)),
+            Some(b'G') => (Note::G, Some(Note::GFlat), Some(Note::GSharp)),
+            Some(b'W') => (Note::W, None, None),
+            _ => return Err(tag_error(input)),
+        };
+        match (input.as_bytes().get(1), flat, sharp) {
+            (Some(b'b'), Some(flat), _) => Ok((&input[2..], flat)),
+            (Some(b'#'), _, Some(sharp)) => Ok((&input[2..], sharp)),
+            _ => Ok((&input[1..], natural)),
+        }
+    }
+}
+
+fn number<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Number> {
+    |input: &'a str| {
+        let (number, length) = match input.as_bytes() {
+            [b'2', ..] => (Number::Two, 1),
+            [b'3', ..] => (Number::Three, 1),
+            [b'5', ..] => (Number::Five, 1),
+            [b'6', ..] => (Number::Six, 1),
+            [b'7', ..] => (Number::Seven, 1),
+            [b'9', ..] => (Number::Nine, 1),
+            [b'1', b'1', ..] => (Number::Eleven, 2),
+            [b'1', b'3', ..] => (Number::Thirteen, 2),
+            _ => return Err(tag_error(input)),
+        };
+        Ok((&input[length..], number))
+    }
+}
+
+fn number_option<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Option<Number>> {
+    opt(number())
+}
+
+fn flavor<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Flavor> {
+    |input: &'a str| {
+        // Longer strings first, then any number after the symbol.
+        let (flavor, length): (fn(Option<Number>) -> Flavor, usize) = match input.as_bytes() {
+            [b'6', b'9', ..] => return Ok((&input[2..], Flavor::SixthNinth)),
+            [b'-', b'6', b'9', ..] => return Ok((&input[3..], Flavor::MinorSixthNinth)),
+            [b'-', b'^', ..] => (Flavor::MinorMajor, 2),
+            [b'-', ..] => (Flavor::Minor, 1),
+            [b'^', ..] => (Flavor::Major, 1),
+            [b'h', ..] => (Flavor::HalfDiminished, 1),
+            [b'o', b'^', ..] => (Flavor::DiminishedMajor, 2),
+            [b'o', ..] => (Flavor::Diminished, 1),
+            [b'+', ..] => (Flavor::Augmented, 1),
+            _ => (Flavor::Dominant, 0),
+        };
+        map(number_option(), flavor)(&input[length..])
+    }
+}
+
+fn altered_notes<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Vec<AlteredNotes>> {
+    // Most chords have none, so don't allocate until one is found.
+    fold_many0(
+        alt((
+            map(tuple((tag("b"), number())), |x| AlteredNotes::Flat(x.1)),
+            map(tuple((tag("#"), number())), |x| AlteredNotes::Sharp(x.1)),
+            map(tuple((tag("add"), number())), |x| AlteredNotes::Add(x.1)),
+            map(tag("sus"), |_| AlteredNotes::Sus),
+            map(tag("alt"), |_| AlteredNotes::Alt),
+            map(
+                tuple((tag("*"), take_until("*"), tag("*"))),
+                |x: (&str, &str, &str)| AlteredNotes::Custom(x.1.to_string()),
+            ),
+        )),
+        Vec::new,
+        |mut notes, note| {
+            notes.push(note);
+            notes
+        },
+    )
+}
+
+fn over<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Option<Note>> {
     alt((
-        map(tag("Ab"), |_| Note::AFlat),
-        map(tag("A#"), |_| Note::ASharp),
-        map(tag("A"), |_| Note::A),
-        map(tag("Bb"), |_| Note::BFlat),
-        map(tag("B"), |_| Note::B),
-        map(tag("Cb"), |_| Note::CFlat),
-        map(tag("C#"), |_| Note::CSharp),
-        map(tag("C"), |_| Note::C),
-        map(tag("Db"), |_| Note::DFlat),
-        map(tag("D#"), |_| Note::DSharp),
-        map(tag("D"), |_| Note::D),
-        map(tag("Eb"), |_| Note::EFlat),
-        map(tag("E"), |_| Note::E),
-        map(tag("F#"), |_| Note::FSharp),
-        map(tag("F"), |_| Note::F),
-        map(tag("Gb"), |_| Note::GFlat),
-        map(tag("G#"), |_| Note::GSharp),
-        map(tag("G"), |_| Note::G),
-        map(tag("W"), |_| Note::W),
+        map(tuple((tag("/"), note())), |x| Some(x.1)),
+        map(tag(""), |_| None),
     ))
 }
 
-fn number<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Number> {
+fn chord<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Chord> {
     alt((
-        map(tag("2"), |_| Number::Two),
-        map(tag("3"), |_| Number::Three),
-        map(tag("5"), |_| Number::Five),
-        map(tag("6"), |_| Number::Six),
-        map(tag("7"), |_| Number::Seven),
-        map(tag("9"), |_| Number::Nine),
-        map(tag("11"), |_| Number::Eleven),
-        map(tag("13"), |_| Number::Thirteen),
+        map(tag("n"), |_| Chord::NC),
+        map(tuple((note(), flavor(), altered_notes(), over())), |x| {
+            Chord::Some {
+                root: x.0,
+                flavor: x.1,
+                altered_notes: x.2,
+                bass_note: x.3,
+            }
+        }),
     ))
 }
 
-fn number_option<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Option<Number>> {
-    alt((map(number(), Some), map(tag(""), |_| None)))
+/* Lenient chord parsing, for chords typed by people rather than iReal. The
+ * input has already had Unicode accidentals replaced by 'b' and '#'. */
+
+fn major_word<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    alt((tag("maj"), tag("Maj"), tag("MA"), tag("ma"), tag("M")))
 }
 
-fn flavor<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Flavor> {
+fn delta_word<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    alt((tag("^"), tag("\u{394}"), tag("\u{25b3}")))
+}
+
+fn minor_word<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    alt((tag("min"), tag("mi"), tag("m"), tag("-")))
+}
+
+fn half_diminished_word<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    alt((tag("h"), tag("\u{f8}"), tag("\u{d8}")))
+}
+
+fn diminished_word<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    alt((tag("dim"), tag("o"), tag("\u{b0}"), tag("\u{ba}")))
+}
+
+fn augmented_word<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    alt((tag("aug"), tag("+")))
+}
+
+fn six_nine<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    alt((tag("6/9"), tag("69")))
+}
+
+fn flat_five<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    alt((tag("7b5"), tag("7(b5)"), tag("7-5")))
+}
+
+fn lenient_flavor<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Flavor> {
     // Put longer strings first.
     alt((
-        map(tag("69"), |_| Flavor::SixthNinth),
-        map(tag("-69"), |_| Flavor::MinorSixthNinth),
-        map(tuple((tag("-^"), number_option())), |x| {
-            Flavor::MinorMajor(x.1)
+        map(six_nine(), |_| Flavor::SixthNinth),
+        map(tuple((minor_word(), six_nine())), |_| {
+            Flavor::MinorSixthNinth
         }),
-        map(tuple((tag("-"), number_option())), |x| Flavor::Minor(x.1)),
-        map(tuple((tag("^"), number_option())), |x| Flavor::Major(x.1)),
-        map(tuple((tag("h"), number_option())), |x| {
-            Flavor::HalfDiminished(x.1)
+        map(
+            tuple((
+                minor_word(),
+                tag("("),
+                alt((major_word(), delta_word())),
+                number_option(),
+                tag(")"),
+            )),
+            |x| Flavor::MinorMajor(x.3),
+        ),
+        map(
+            tuple((
+                minor_word(),
+                alt((major_word(), delta_word())),
+                number_option(),
+            )),
+            |x| Flavor::MinorMajor(x.2),
+        ),
+        map(tuple((minor_word(), flat_five())), |_| {
+            Flavor::HalfDiminished(Some(Number::Seven))
         }),
-        map(tuple((tag("o^"), number_option())), |x| {
-            Flavor::DiminishedMajor(x.1)
+        map(tuple((half_diminished_word(), number_option())), |x| {
+            Flavor::HalfDiminished(x.1)
         }),
-        map(tuple((tag("o"), number_option())), |x| {
+        map(
+            tuple((
+                diminished_word(),
+                alt((major_word(), delta_word())),
+                number_option(),
+            )),
+            |x| Flavor::DiminishedMajor(x.2),
+        ),
+        map(tuple((diminished_word(), number_option())), |x| {
             Flavor::Diminished(x.1)
         }),
-        map(tuple((tag("+"), number_option())), |x| {
+        map(tuple((augmented_word(), number_option())), |x| {
             Flavor::Augmented(x.1)
         }),
+        map(tuple((delta_word(), number_option())), |x| {
+            Flavor::Major(x.1)
+        }),
+        // "Cmaj" is just a triad, but "Cmaj7" is a major seventh.
+        map(tuple((major_word(), number_option())), |x| match x.1 {
+            Some(n) => Flavor::Major(Some(n)),
+            None => Flavor::Dominant(None),
+        }),
+        map(tuple((minor_word(), number_option())), |x| {
+            Flavor::Minor(x.1)
+        }),
         map(number_option(), Flavor::Dominant),
     ))
 }
 
-fn altered_notes<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Vec<AlteredNotes>> {
-    many0(alt((
-        map(tuple((tag("b"), number())), |x| AlteredNotes::Flat(x.1)),
-        map(tuple((tag("#"), number())), |x| AlteredNotes::Sharp(x.1)),
+fn lenient_alteration<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, AlteredNotes> {
+    alt((
+        map(tuple((alt((tag("b"), tag("-"))), number())), |x| {
+            AlteredNotes::Flat(x.1)
+        }),
+        map(tuple((alt((tag("#"), tag("+"))), number())), |x| {
+            AlteredNotes::Sharp(x.1)
+        }),
         map(tuple((tag("add"), number())), |x| AlteredNotes::Add(x.1)),
-        map(tag("sus"), |_| AlteredNotes::Sus),
+        map(tag("sus2"), |_| AlteredNotes::Custom("sus2".to_string())),
+        map(tuple((tag("sus"), opt(tag("4")))), |_| AlteredNotes::Sus),
         map(tag("alt"), |_| AlteredNotes::Alt),
-        map(
-            tuple((tag("*"), take_until("*"), tag("*"))),
-            |x: (&str, &str, &str)| AlteredNotes::Custom(x.1.to_string()),
-        ),
-    )))
+    ))
 }
 
-fn over<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Option<Note>> {
-    alt((
-        map(tuple((tag("/"), note())), |x| Some(x.1)),
-        map(tag(""), |_| None),
-    ))
+fn lenient_altered_notes<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Vec<AlteredNotes>> {
+    map(
+        many0(alt((
+            // "C7(b9,#11)"
+            map(
+                tuple((
+                    tag("("),
+                    separated_list1(alt((tag(","), tag(" "))), lenient_alteration()),
+                    tag(")"),
+                )),
+                |x| x.1,
+            ),
+            map(lenient_alteration(), |x| vec![x]),
+        ))),
+        |x| x.into_iter().flatten().collect(),
+    )
 }
 
-fn chord<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Chord> {
+fn lenient_chord<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Chord> {
     alt((
-        map(tag("n"), |_| Chord::NC),
-        map(tuple((note(), flavor(), altered_notes(), over())), |x| {
-            Chord::Some {
+        map(alt((tag("N.C."), tag("NC"), tag("n"))), |_| Chord::NC),
+        map(
+            tuple((note(), lenient_flavor(), lenient_altered_notes(), over())),
+            |x| Chord::Some {
                 root: x.0,
                 flavor: x.1,
                 altered_notes: x.2,
                 bass_note: x.3,
-            }
-        }),
+            },
+        ),
     ))
 }
 
+/// Parse a single chord, either in iReal's notation or in one of the common
+/// ways people write chords.
+pub fn parse_chord(text: &str) -> Result<Chord, String> {
+    let text = text.trim();
+    if let Ok((_, chord)) = all_consuming(chord())(text) {
+        return Ok(chord);
+    }
+    let normalized = text.replace('\u{266d}', "b").replace('\u{266f}', "#");
+    let result = all_consuming(lenient_chord())(normalized.as_str());
+    match result {
+        Ok((_, chord)) => Ok(chord),
+        Err(_) => Err(format!("Unrecognized chord: {:?}", text)),
+    }
+}
+
 fn chord_token<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token> {
     alt((
         map(tag("n"), |_| Token::Chord(Chord::NC)),
@@ -203,63 +453,230 @@ fn chord_token<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token> {
 fn time_signature<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token> {
     /* The only signatures in jazz1400 are: T24, T34, T44, T54, T64. */
     /* Assume top number can be multiple digits, and the bottom number is a
-     * single digit. */
-    map(tuple((tag("T"), digit1)), |x| {
+     * single digit, except that iReal writes 12/8 as T12. */
+    map_res(tuple((tag("T"), digit1)), |x| {
         let digits: &str = x.1;
+        if digits == "12" {
+            return Ok(Token::TimeSignature(12, 8));
+        }
         let (top, bottom) = digits.split_at(digits.len() - 1);
-        let top_num = top.parse().unwrap();
-        let bottom_num = bottom.parse().unwrap();
-        Token::TimeSignature(top_num, bottom_num)
+        Ok::<_, std::num::ParseIntError>(Token::TimeSignature(top.parse()?, bottom.parse()?))
     })
 }
 
-fn control<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token> {
-    alt((
-        map(tag("{"), |_| Token::RepeatStart),
-        map(tag("}|"), |_| Token::RepeatEnd),
-        map(tag("}"), |_| Token::RepeatEnd),
-        map(tag(","), |_| Token::Comma),
-        map(tag("XyQ"), |_| Token::Blank),
-        map(tag("r|"), |_| Token::RepeatTwoMeasures),
-        map(tag("x"), |_| Token::RepeatMeasure),
-        map(tag("s"), |_| Token::Squeeze),
-        map(tag("Q"), |_| Token::Coda),
-        map(tag("S"), |_| Token::Segno),
-        map(tag("Y"), |_| Token::VerticalSpace),
-        map(tag("p"), |_| Token::PauseSlash),
-        map(tag("U"), |_| Token::EndingMeasure),
-        map(tag("l"), |_| Token::Unsqueeze),
-        map(tag("f"), |_| Token::Fermata),
-        map(tag(" "), |_| Token::Space),
-    ))
+fn control<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token<'a>> {
+    |input: &'a str| {
+        let (token, length) = match input.as_bytes() {
+            [b'{', ..] => (Token::RepeatStart, 1),
+            [b'}', b'|', ..] => (Token::RepeatEnd, 2),
+            [b'}', ..] => (Token::RepeatEnd, 1),
+            [b',', ..] => (Token::Comma, 1),
+            [b'X', b'y', b'Q', ..] => (Token::Blank, 3),
+            [b'r', b'|', ..] => (Token::RepeatTwoMeasures, 2),
+            [b'x', ..] => (Token::RepeatMeasure, 1),
+            [b's', ..] => (Token::Squeeze, 1),
+            [b'Q', ..] => (Token::Coda, 1),
+            [b'S', ..] => (Token::Segno, 1),
+            [b'Y', ..] => (Token::VerticalSpace, 1),
+            [b'p', ..] => (Token::PauseSlash, 1),
+            [b'U', ..] => (Token::EndingMeasure, 1),
+            [b'l', ..] => (Token::Unsqueeze, 1),
+            [b'f', ..] => (Token::Fermata, 1),
+            [b' ', ..] => (Token::Space, 1),
+            _ => return Err(tag_error(input)),
+        };
+        Ok((&input[length..], token))
+    }
 }
 
-fn bar_line<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token> {
-    alt((
-        map(tag("||"), |_| Token::Bar),
-        map(tag("|"), |_| Token::Bar),
-        map(tag("["), |_| Token::DoubleBarStart),
-        map(tag("]"), |_| Token::DoubleBarEnd),
-        map(tag("Z"), |_| Token::FinalBar),
-        map(tag("Kcl"), |_| Token::BarAndRepeat),
-        map(tag("LZ|"), |_| Token::Bar),
-        map(tag("LZ"), |_| Token::Bar),
-    ))
+fn bar_line<'a>() -> impl FnMut(&'a str) -> IResult<&'a str, Token<'a>> {
+    |input: &'a str| {
+        let (token, length) = match input.as_bytes() {
+            [b'|', b'|', ..] => (Token::Bar, 2),
+            [b'|', ..] => (Token::Bar, 1),
+            [b'[', ..] => (Token::DoubleBarStart, 1),
+            [b']', ..] => (Token::DoubleBarEnd, 1),
+            [b'Z', ..] => (Token::FinalBar, 1),
+            [b'K', b'c', b'l', ..] => (Token::BarAndRepeat, 3),
+            _ => return Err(tag_error(input)),
+        };
+        Ok((&input[length..], token))
+    }
+}
+
+type Pair<'a> = (Token<'a>, Option<Token<'a>>);
+
+fn single<'a>(result: IResult<&'a str, Token<'a>>) -> IResult<&'a str, Pair<'a>> {
+    result.map(|(rest, token)| (rest, (token, None)))
+}
+
+/* One piece of music text, as the tokens it stands for. Most pieces are a
+ * single token, but LZ is a space and a barline. The first character is
+ * enough to tell which kind of piece is next. */
+fn lexeme(input: &str) -> IResult<&str, Pair<'_>> {
+    match input.as_bytes().first() {
+        // LZ stands for " |", so the space takes up a cell.
+        Some(b'L') => map(alt((tag("LZ|"), tag("LZ"))), |_| {
+            (Token::Space, Some(Token::Bar))
+        })(input),
+        Some(b'A'..=b'G' | b'W' | b'n') => single(chord_token()(input)),
+        Some(b'|' | b'[' | b']' | b'Z' | b'K') => single(bar_line()(input)),
+        Some(b'<') => single(comment()(input)),
+        Some(b'(') => single(alternate()(input)),
+        Some(b'*') => single(section_marker()(input)),
+        Some(b'N') => single(numbered_ending()(input)),
+        Some(b'T') => single(time_signature()(input)),
+        _ => single(control()(input)),
+    }
+}
+
+/// A piece of music text's byte range and the tokens it stands for.
+pub(crate) type Lexeme<'a> = (Range<usize>, Token<'a>, Option<Token<'a>>);
+
+/// A token and the bytes of music text it was read from. Tokens read from
+/// the same text, like the space and barline of `LZ`, share its span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned<'a> {
+    pub token: Token<'a>,
+    pub span: Range<usize>,
+}
+
+// Characters of music shown either side of an error.
+const SNIPPET_CONTEXT: usize = 20;
+
+/// A problem reading music text, and where it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MusicError {
+    pub message: String,
+    /// Byte offset of the problem in the unscrambled music text.
+    pub offset: usize,
+    /// The music around the problem, which is `column` characters in.
+    pub snippet: String,
+    pub column: usize,
+    pub title: Option<String>,
+}
+
+impl MusicError {
+    pub(crate) fn new(message: &str, text: &str, offset: usize) -> MusicError {
+        let before: Vec<char> = text[..offset].chars().collect();
+        let skip = before.len().saturating_sub(SNIPPET_CONTEXT);
+        let mut snippet: String = before[skip..].iter().collect();
+        snippet.extend(text[offset..].chars().take(SNIPPET_CONTEXT));
+        MusicError {
+            message: message.to_string(),
+            offset,
+            snippet,
+            column: before.len() - skip,
+            title: None,
+        }
+    }
+
+    /// The same error, for the song with this title.
+    pub fn with_title(self, title: &str) -> MusicError {
+        MusicError {
+            title: Some(title.to_string()),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for MusicError {
+    /// The message, then the snippet with a caret under the problem.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)?;
+        if let Some(title) = &self.title {
+            write!(f, " of {:?}", title)?;
+        }
+        write!(f, "\n  {}\n  {}^", self.snippet, " ".repeat(self.column))
+    }
+}
+
+impl From<MusicError> for String {
+    fn from(error: MusicError) -> String {
+        error.to_string()
+    }
+}
+
+/// Split music text into pieces. Together the ranges cover every byte of
+/// the text.
+pub(crate) fn lex(input: &str) -> Result<Vec<Lexeme<'_>>, MusicError> {
+    lex_with(input, None)
+}
+
+/* With somewhere to put warnings, text that can't be read is kept as
+ * `Token::Unknown`, with a warning for each run of it, rather than being an
+ * error. */
+fn lex_with<'a>(
+    input: &'a str,
+    mut warnings: Option<&mut Vec<MusicError>>,
+) -> Result<Vec<Lexeme<'a>>, MusicError> {
+    let mut lexemes: Vec<Lexeme> = vec![];
+    let mut rest = input;
+    while !rest.is_empty() {
+        let start = input.len() - rest.len();
+        match lexeme(rest) {
+            Ok((next, (token, second))) if next.len() < rest.len() => {
+                lexemes.push((start..input.len() - next.len(), token, second));
+                rest = next;
+            }
+            _ => {
+                let error = MusicError::new("Unrecognized music", input, start);
+                let Some(warnings) = warnings.as_mut() else {
+                    return Err(error);
+                };
+                let length = rest.chars().next().map_or(1, char::len_utf8);
+                rest = &rest[length..];
+                // Runs of unreadable text are kept together.
+                if let Some((span, Token::Unknown(unknown), _)) = lexemes.last_mut() {
+                    span.end += length;
+                    *unknown = Cow::Borrowed(&input[span.clone()]);
+                    continue;
+                }
+                warnings.push(error);
+                let span = start..start + length;
+                lexemes.push((
+                    span.clone(),
+                    Token::Unknown(Cow::Borrowed(&input[span])),
+                    None,
+                ));
+            }
+        }
+    }
+    Ok(lexemes)
+}
+
+fn spanned(lexemes: Vec<Lexeme>) -> Vec<Spanned> {
+    let mut tokens = Vec::with_capacity(lexemes.len());
+    for (span, token, second) in lexemes {
+        if let Some(second) = second {
+            tokens.push(Spanned {
+                token,
+                span: span.clone(),
+            });
+            tokens.push(Spanned {
+                token: second,
+                span,
+            });
+        } else {
+            tokens.push(Spanned { token, span });
+        }
+    }
+    tokens
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Spanned<'_>>, MusicError> {
+    Ok(spanned(lex(input)?))
 }
 
-fn tokens(input: &str) -> IResult<&str, Vec<Token>> {
-    many0(alt((
-        chord_token(),
-        bar_line(),
-        control(),
-        comment(),
-        alternate(),
-        section_marker(),
-        numbered_ending(),
-        time_signature(),
-    )))(input)
+/// Tokens for all of the music, with a warning for each part that couldn't
+/// be read.
+pub fn tokenize_lenient(input: &str) -> (Vec<Spanned<'_>>, Vec<MusicError>) {
+    let (lexemes, warnings) = lex_lenient(input);
+    (spanned(lexemes), warnings)
 }
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
-    Ok(all_consuming(tokens)(input).unwrap().1)
+pub(crate) fn lex_lenient(input: &str) -> (Vec<Lexeme<'_>>, Vec<MusicError>) {
+    let mut warnings = vec![];
+    let lexemes = lex_with(input, Some(&mut warnings)).unwrap_or_default();
+    (lexemes, warnings)
 }
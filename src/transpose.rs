@@ -0,0 +1,263 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    parse::{CellContent, Music, WrittenElement},
+    syntax::SyntaxTree,
+    tokenize::Token,
+    types::{Chord, Key, Note},
+    Collection, Song,
+};
+
+/// The instrument a part is written for, and so how far the chords are
+/// transposed from concert pitch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instrument {
+    Concert,
+    /// Trumpet, clarinet, tenor and soprano sax.
+    BFlat,
+    /// Alto and baritone sax.
+    EFlat,
+    /// French horn.
+    F,
+    /// Trombone, bass and tuba read concert pitch, but get their own part.
+    BassClef,
+}
+
+impl Instrument {
+    /// Semitones up from concert pitch to the written part.
+    pub fn semitones(&self) -> i32 {
+        match self {
+            Instrument::Concert => 0,
+            Instrument::BFlat => 2,
+            Instrument::EFlat => 9,
+            Instrument::F => 7,
+            Instrument::BassClef => 0,
+        }
+    }
+
+    /// The name printed on a part, or `None` for concert pitch.
+    pub fn label(&self) -> Option<String> {
+        match self {
+            Instrument::Concert => None,
+            Instrument::BassClef => Some("Bass Clef".to_string()),
+            instrument => Some(format!("{} Instruments", instrument)),
+        }
+    }
+}
+
+impl fmt::Display for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instrument::Concert => "Concert",
+            Instrument::BFlat => "Bb",
+            Instrument::EFlat => "Eb",
+            Instrument::F => "F",
+            Instrument::BassClef => "Bass Clef",
+        }
+        .fmt(f)
+    }
+}
+
+impl FromStr for Instrument {
+    type Err = String;
+
+    /// Accepts the key of the instrument or a common instrument name, e.g.
+    /// "bb", "tenor" or "horn".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "concert" | "c" | "piano" | "guitar" => Ok(Instrument::Concert),
+            "bb" | "b-flat" | "trumpet" | "clarinet" | "tenor" | "soprano" => Ok(Instrument::BFlat),
+            "eb" | "e-flat" | "alto" | "bari" | "baritone" => Ok(Instrument::EFlat),
+            "f" | "horn" => Ok(Instrument::F),
+            "bass" | "bass-clef" | "trombone" | "tuba" => Ok(Instrument::BassClef),
+            _ => Err(format!("Unknown instrument: {:?}", s)),
+        }
+    }
+}
+
+/// A song transposed for an instrument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub instrument: Instrument,
+    /// The song in the instrument's written key.
+    pub song: Song,
+}
+
+impl Part {
+    /// The part as a plain song, with the instrument added to the title so
+    /// parts can sit side by side in iReal.
+    pub fn to_song(&self) -> Song {
+        let mut song = self.song.clone();
+        if self.instrument != Instrument::Concert {
+            song.title = format!("{} ({})", song.title, self.instrument);
+        }
+        song
+    }
+}
+
+impl Note {
+    /// The note `semitones` higher, or lower if negative.
+    pub fn transpose(&self, semitones: i32, prefer_flats: bool) -> Note {
+        self.move_by(semitones, |pc| Note::from_pitch_class(pc, prefer_flats))
+    }
+
+    /// The note `semitones` higher, spelled for its place in `key`.
+    pub fn transpose_in(&self, semitones: i32, key: &Key) -> Note {
+        self.move_by(semitones, |pc| key.spell(pc))
+    }
+
+    fn move_by(&self, semitones: i32, spell: impl Fn(u8) -> Note) -> Note {
+        match self.pitch_class() {
+            Some(pc) => spell((pc as i32 + semitones).rem_euclid(12) as u8),
+            None => self.clone(),
+        }
+    }
+}
+
+impl Chord {
+    /// The chord with its root and bass note moved by `semitones`.
+    pub fn transpose(&self, semitones: i32, prefer_flats: bool) -> Chord {
+        self.move_notes(|note| note.transpose(semitones, prefer_flats))
+    }
+
+    /// The chord moved by `semitones`, with its root and bass note spelled
+    /// for their place in `key`, the key it's moved into.
+    pub fn transpose_in(&self, semitones: i32, key: &Key) -> Chord {
+        self.move_notes(|note| note.transpose_in(semitones, key))
+    }
+
+    fn move_notes(&self, move_note: impl Fn(&Note) -> Note) -> Chord {
+        match self {
+            Chord::NC => Chord::NC,
+            Chord::Some {
+                root,
+                flavor,
+                altered_notes,
+                bass_note,
+            } => Chord::Some {
+                root: move_note(root),
+                flavor: flavor.clone(),
+                altered_notes: altered_notes.clone(),
+                bass_note: bass_note.as_ref().map(move_note),
+            },
+        }
+    }
+}
+
+impl Key {
+    /// The key `semitones` higher, spelled the usual way.
+    pub fn transpose(&self, semitones: i32) -> Key {
+        match self.tonic.pitch_class() {
+            Some(pc) => {
+                Key::from_pitch_class((pc as i32 + semitones).rem_euclid(12) as u8, self.minor)
+            }
+            None => self.clone(),
+        }
+    }
+}
+
+/* Rewrites only the chords in music text, so the rest keeps its spelling. */
+fn move_chords(text: &str, move_chord: impl Fn(&Chord) -> Chord) -> Result<String, String> {
+    let mut tree = SyntaxTree::parse_lenient(text);
+    for index in (0..tree.nodes().len()).rev() {
+        let moved = match tree.nodes()[index].tokens.as_slice() {
+            [Token::Chord(chord)] => Token::Chord(move_chord(chord)),
+            [Token::AlternateChord(chord)] => Token::AlternateChord(move_chord(chord)),
+            _ => continue,
+        };
+        tree.replace(index, &moved.to_string())?;
+    }
+    Ok(tree.text().to_string())
+}
+
+impl Music {
+    /// The music with every chord moved by `semitones`. `raw` is rewritten
+    /// to match, unless the chords don't move.
+    pub fn transpose(&self, semitones: i32, prefer_flats: bool) -> Music {
+        self.map_chords(semitones, |chord| chord.transpose(semitones, prefer_flats))
+    }
+
+    /// The music moved by `semitones` into `key`, with each chord spelled
+    /// for its place in the key.
+    pub fn transpose_in(&self, semitones: i32, key: &Key) -> Music {
+        self.map_chords(semitones, |chord| chord.transpose_in(semitones, key))
+    }
+
+    fn map_chords(&self, semitones: i32, move_chord: impl Fn(&Chord) -> Chord) -> Music {
+        if semitones.rem_euclid(12) == 0 {
+            return self.clone();
+        }
+        let raw = move_chords(&self.raw, &move_chord).unwrap_or_else(|_| self.raw.clone());
+        let mut written_bars = self.written_bars.clone();
+        for bar in &mut written_bars {
+            for cell in &mut bar.cells {
+                if let Some(CellContent::Chord(chord, _)) = &mut cell.content {
+                    *chord = move_chord(chord);
+                }
+                if let Some(alternate) = &mut cell.alternate {
+                    *alternate = move_chord(alternate);
+                }
+            }
+            for element in &mut bar.elements {
+                match element {
+                    WrittenElement::Chord(chord, _) | WrittenElement::AlternateChord(chord) => {
+                        *chord = move_chord(chord)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Music { raw, written_bars }
+    }
+}
+
+impl Song {
+    /// The song moved by `semitones`, with chords spelled to suit the new
+    /// key. Moving by whole octaves leaves the song as it was written.
+    pub fn transpose(&self, semitones: i32) -> Song {
+        if semitones.rem_euclid(12) == 0 {
+            return self.clone();
+        }
+        let key: Option<Key> = self.key.parse().ok();
+        let new_key = key.map(|key| key.transpose(semitones));
+        let music = match &new_key {
+            Some(key) => self.music.transpose_in(semitones, key),
+            None => self.music.transpose(semitones, true),
+        };
+        Song {
+            key: new_key.map_or(self.key.clone(), |key| key.to_string()),
+            music,
+            ..self.clone()
+        }
+    }
+}
+
+impl Song {
+    /// The part for `instrument`, with chords and key transposed. Concert
+    /// and bass clef parts keep the music exactly as written.
+    pub fn part(&self, instrument: Instrument) -> Part {
+        Part {
+            instrument,
+            song: self.transpose(instrument.semitones()),
+        }
+    }
+}
+
+impl Collection {
+    /// Every song as a part for `instrument`, with the instrument in the
+    /// song and collection titles.
+    pub fn part(&self, instrument: Instrument) -> Collection {
+        let title = match instrument {
+            Instrument::Concert => self.title.clone(),
+            instrument => format!("{} ({})", self.title, instrument),
+        };
+        Collection {
+            title,
+            songs: self
+                .songs
+                .iter()
+                .map(|song| song.part(instrument).to_song())
+                .collect(),
+        }
+    }
+}
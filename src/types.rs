@@ -1,4 +1,6 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
+
+use crate::tokenize;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TimeSignature {
@@ -63,6 +65,164 @@ impl fmt::Display for Note {
     }
 }
 
+impl Note {
+    /// Semitones above C, or None for `W`.
+    pub fn pitch_class(&self) -> Option<u8> {
+        match self {
+            Note::C => Some(0),
+            Note::CSharp | Note::DFlat => Some(1),
+            Note::D => Some(2),
+            Note::DSharp | Note::EFlat => Some(3),
+            Note::E => Some(4),
+            Note::F => Some(5),
+            Note::FSharp | Note::GFlat => Some(6),
+            Note::G => Some(7),
+            Note::GSharp | Note::AFlat => Some(8),
+            Note::A => Some(9),
+            Note::ASharp | Note::BFlat => Some(10),
+            Note::B | Note::CFlat => Some(11),
+            Note::W => None,
+        }
+    }
+
+    pub fn from_pitch_class(pitch_class: u8, prefer_flats: bool) -> Note {
+        match (pitch_class % 12, prefer_flats) {
+            (0, _) => Note::C,
+            (1, true) => Note::DFlat,
+            (1, false) => Note::CSharp,
+            (2, _) => Note::D,
+            (3, true) => Note::EFlat,
+            (3, false) => Note::DSharp,
+            (4, _) => Note::E,
+            (5, _) => Note::F,
+            (6, true) => Note::GFlat,
+            (6, false) => Note::FSharp,
+            (7, _) => Note::G,
+            (8, true) => Note::AFlat,
+            (8, false) => Note::GSharp,
+            (9, _) => Note::A,
+            (10, true) => Note::BFlat,
+            (10, false) => Note::ASharp,
+            _ => Note::B,
+        }
+    }
+
+    pub fn is_flat(&self) -> bool {
+        matches!(
+            self,
+            Note::AFlat | Note::BFlat | Note::CFlat | Note::DFlat | Note::EFlat | Note::GFlat
+        )
+    }
+}
+
+impl FromStr for Note {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let note = match s {
+            "Ab" => Note::AFlat,
+            "A" => Note::A,
+            "A#" => Note::ASharp,
+            "Bb" => Note::BFlat,
+            "B" => Note::B,
+            "Cb" => Note::CFlat,
+            "C" => Note::C,
+            "C#" => Note::CSharp,
+            "Db" => Note::DFlat,
+            "D" => Note::D,
+            "D#" => Note::DSharp,
+            "Eb" => Note::EFlat,
+            "E" => Note::E,
+            "F" => Note::F,
+            "F#" => Note::FSharp,
+            "Gb" => Note::GFlat,
+            "G" => Note::G,
+            "G#" => Note::GSharp,
+            _ => return Err(format!("Unknown note: {:?}", s)),
+        };
+        Ok(note)
+    }
+}
+
+/// A key as iReal writes it in `Song.key`, e.g. "Eb" or "C-".
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub tonic: Note,
+    pub minor: bool,
+}
+
+impl Key {
+    /// The key on `pitch_class`, spelled the way it's most commonly written.
+    pub fn from_pitch_class(pitch_class: u8, minor: bool) -> Key {
+        let tonic = match (pitch_class % 12, minor) {
+            (1, false) => Note::DFlat,
+            (1, true) => Note::CSharp,
+            (3, _) => Note::EFlat,
+            (6, false) => Note::GFlat,
+            (6, true) => Note::FSharp,
+            (8, false) => Note::AFlat,
+            (8, true) => Note::GSharp,
+            (10, _) => Note::BFlat,
+            (pitch_class, _) => Note::from_pitch_class(pitch_class, false),
+        };
+        Key { tonic, minor }
+    }
+
+    /// Whether the key signature has flats, and so accidentals in this key
+    /// should be spelled with flats.
+    pub fn prefers_flats(&self) -> bool {
+        if self.tonic.is_flat() {
+            return true;
+        }
+        if self.minor {
+            matches!(self.tonic, Note::C | Note::D | Note::F | Note::G)
+        } else {
+            self.tonic == Note::F
+        }
+    }
+}
+
+impl Key {
+    /// How `pitch_class` is spelled in this key. The borrowed flat degrees
+    /// of jazz harmony, bII, bIII, bVI and bVII, keep their flats even in
+    /// sharp keys, and the leading tone is never a flat. Other notes follow
+    /// the key signature.
+    pub fn spell(&self, pitch_class: u8) -> Note {
+        let degree = self
+            .tonic
+            .pitch_class()
+            .map(|tonic| (pitch_class % 12 + 12 - tonic) % 12);
+        let prefer_flats = match degree {
+            Some(1 | 3 | 8 | 10) => true,
+            Some(11) => false,
+            _ => self.prefers_flats(),
+        };
+        Note::from_pitch_class(pitch_class, prefer_flats)
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (tonic, minor) = match s.strip_suffix('-').or_else(|| s.strip_suffix('m')) {
+            Some(tonic) => (tonic, true),
+            None => (s, false),
+        };
+        Ok(Key {
+            tonic: tonic.parse()?,
+            minor,
+        })
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.tonic, if self.minor { "-" } else { "" })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Number {
     Two,
@@ -142,6 +302,16 @@ impl fmt::Display for Chord {
     }
 }
 
+impl FromStr for Chord {
+    type Err = String;
+
+    /// Accepts iReal notation ("C^7", "C-7", "Ch7") as well as common
+    /// spellings like "Cmaj7", "Cm7", "C\u{f8}7", "C\u{b0}7" or "C7(b9)".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        tokenize::parse_chord(s)
+    }
+}
+
 impl fmt::Debug for Chord {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         format!("{}", self).fmt(f)
@@ -198,7 +368,7 @@ impl fmt::Display for Flavor {
                 None => "^".to_string(),
             },
             Flavor::SixthNinth => "69".to_string(),
-            Flavor::MinorSixthNinth => "m69".to_string(),
+            Flavor::MinorSixthNinth => "-69".to_string(),
         }
         .fmt(f)
     }
@@ -222,7 +392,7 @@ impl fmt::Display for AlteredNotes {
             AlteredNotes::Add(n) => format!("add{}", n),
             AlteredNotes::Sus => "sus".to_string(),
             AlteredNotes::Alt => "alt".to_string(),
-            AlteredNotes::Custom(s) => s.to_string(),
+            AlteredNotes::Custom(s) => format!("*{}*", s),
         }
         .fmt(f)
     }
@@ -0,0 +1,297 @@
+use std::fmt;
+
+use crate::{
+    analysis::Quality,
+    parse::Music,
+    types::{Chord, Note},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoicingStyle {
+    /// Root, third and seventh.
+    Shell,
+    /// Rootless voicings starting on the third: 3 5 7 9, or 3 13 7 9 on
+    /// dominants.
+    RootlessA,
+    /// Rootless voicings starting on the seventh: 7 9 3 5, or 7 9 3 13.
+    RootlessB,
+    /// Whichever of the A and B rootless voicings leads best.
+    Rootless,
+    /// Four note chords with the second voice from the top dropped an
+    /// octave.
+    Drop2,
+    /// Three fourths and a major third on top. Only minor chords get this
+    /// voicing; other chords fall back to rootless voicings.
+    SoWhat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoicingOptions {
+    pub style: VoicingStyle,
+    /// Lowest and highest MIDI notes a voicing may use.
+    pub low: u8,
+    pub high: u8,
+}
+
+impl Default for VoicingOptions {
+    fn default() -> Self {
+        VoicingOptions {
+            style: VoicingStyle::Rootless,
+            // Around middle C, where left hand voicings sound clear.
+            low: 48,
+            high: 74,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Voicing {
+    pub chord: Chord,
+    /// Index into `Music.written_bars`.
+    pub bar: usize,
+    pub beat: u32,
+    /// MIDI note numbers, lowest first. Middle C is 60.
+    pub notes: Vec<u8>,
+}
+
+impl fmt::Display for Voicing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefer_flats = match &self.chord {
+            Chord::Some { root, .. } => root.is_flat() || *root == Note::F,
+            Chord::NC => true,
+        };
+        write!(f, "{}:", self.chord)?;
+        for note in &self.notes {
+            write!(
+                f,
+                " {}{}",
+                Note::from_pitch_class(note % 12, prefer_flats),
+                (*note as i32 / 12) - 1
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/* The chord tones a voicing picks from, as semitones above the root. */
+pub(crate) struct Tones {
+    pub(crate) third: u8,
+    pub(crate) fifth: u8,
+    pub(crate) seventh: u8,
+    pub(crate) ninth: u8,
+    // The 13th on dominants, otherwise the fifth.
+    pub(crate) color: u8,
+}
+
+pub(crate) fn tones(chord: &Chord) -> Option<Tones> {
+    let quality = chord.quality()?;
+    let intervals = chord.intervals();
+    let has = |i: u8| intervals.contains(&i);
+    let third = [4, 3, 5, 2].into_iter().find(|&i| has(i)).unwrap_or(4);
+    let fifth = [7, 6, 8].into_iter().find(|&i| has(i)).unwrap_or(7);
+    let seventh = match [10, 11, 9].into_iter().find(|&i| has(i)) {
+        Some(seventh) => seventh,
+        // Triads get a sixth or a minor seventh to fill out the voicing.
+        None if quality == Quality::Minor => 10,
+        None => 9,
+    };
+    let ninth = if has(1) {
+        1
+    } else if has(3) && third == 4 {
+        3
+    } else {
+        2
+    };
+    let color = if quality == Quality::Dominant && fifth == 7 {
+        if has(8) {
+            8
+        } else {
+            9
+        }
+    } else {
+        fifth
+    };
+    Some(Tones {
+        third,
+        fifth,
+        seventh,
+        ninth,
+        color,
+    })
+}
+
+/// Stack intervals upwards, so each is above the one before it.
+fn stack(intervals: &[u8]) -> Vec<u8> {
+    let mut stacked: Vec<u8> = vec![];
+    for &interval in intervals {
+        let mut note = interval;
+        while let Some(&previous) = stacked.last() {
+            if note > previous {
+                break;
+            }
+            note += 12;
+        }
+        stacked.push(note);
+    }
+    stacked
+}
+
+/// Voicing shapes for `chord`, as semitones above the root.
+fn shapes(chord: &Chord, style: VoicingStyle) -> Vec<Vec<u8>> {
+    let tones = match tones(chord) {
+        Some(tones) => tones,
+        None => return vec![],
+    };
+    let a = stack(&[tones.third, tones.color, tones.seventh, tones.ninth]);
+    let b = stack(&[tones.seventh, tones.ninth, tones.third, tones.color]);
+    match style {
+        VoicingStyle::Shell => vec![
+            stack(&[0, tones.third, tones.seventh]),
+            stack(&[0, tones.seventh, tones.third]),
+        ],
+        VoicingStyle::RootlessA => vec![a],
+        VoicingStyle::RootlessB => vec![b],
+        VoicingStyle::Rootless => vec![a, b],
+        VoicingStyle::Drop2 => {
+            let close = [0, tones.third, tones.fifth, tones.seventh];
+            (0..4)
+                .map(|inversion| {
+                    let close = stack(&[
+                        close[inversion],
+                        close[(inversion + 1) % 4],
+                        close[(inversion + 2) % 4],
+                        close[(inversion + 3) % 4],
+                    ]);
+                    // Drop the second voice from the top by an octave,
+                    // raising the rest so nothing goes below the root.
+                    let mut voices: Vec<u8> = close
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &v)| if i == 2 { v } else { v + 12 })
+                        .collect();
+                    voices.sort();
+                    voices
+                })
+                .collect()
+        }
+        VoicingStyle::SoWhat => {
+            if chord.quality() == Some(Quality::Minor) {
+                vec![vec![0, 5, 10, 15, 19]]
+            } else {
+                vec![a, b]
+            }
+        }
+    }
+}
+
+/// Every placement of `chord` in the register, as MIDI notes.
+fn candidates(chord: &Chord, options: &VoicingOptions) -> Vec<Vec<u8>> {
+    let root = match chord.root_pitch_class() {
+        Some(root) => root,
+        None => return vec![],
+    };
+    let mut candidates = vec![];
+    for shape in shapes(chord, options.style) {
+        for octave in 0..11u8 {
+            let base = octave as u16 * 12 + root as u16;
+            let notes: Vec<u16> = shape.iter().map(|&i| base + i as u16).collect();
+            if notes
+                .iter()
+                .all(|&n| n >= options.low as u16 && n <= options.high as u16)
+            {
+                candidates.push(notes.into_iter().map(|n| n as u8).collect());
+            }
+        }
+    }
+    candidates
+}
+
+/// How far the voices move between two voicings, in semitones.
+pub(crate) fn movement(from: &[u8], to: &[u8]) -> u32 {
+    if from.len() == to.len() {
+        return from
+            .iter()
+            .zip(to)
+            .map(|(a, b)| a.abs_diff(*b) as u32)
+            .sum();
+    }
+    // Different sizes: each note moves to the nearest note of the other.
+    let nearest = |note: &u8, others: &[u8]| -> u32 {
+        others
+            .iter()
+            .map(|other| note.abs_diff(*other) as u32)
+            .min()
+            .unwrap_or(0)
+    };
+    from.iter().map(|n| nearest(n, to)).sum::<u32>()
+        + to.iter().map(|n| nearest(n, from)).sum::<u32>()
+}
+
+impl Music {
+    /// Voice every chord, choosing voicings that move as little as possible
+    /// from one chord to the next. Chords that can't be voiced in the
+    /// register, like N.C., are left out.
+    pub fn voicings(&self, options: &VoicingOptions) -> Vec<Voicing> {
+        let timeline: Vec<_> = self.timeline();
+        let chords: Vec<(usize, Vec<Vec<u8>>)> = timeline
+            .iter()
+            .enumerate()
+            .map(|(i, timed)| (i, candidates(&timed.chord, options)))
+            .filter(|(_, candidates)| !candidates.is_empty())
+            .collect();
+        if chords.is_empty() {
+            return vec![];
+        }
+
+        // Find the path through the candidates with the least total
+        // movement. The first voicing is the one nearest the middle of the
+        // register.
+        let middle = (options.low as u32 + options.high as u32) / 2;
+        let mut cost: Vec<u32> = chords[0]
+            .1
+            .iter()
+            .map(|notes| {
+                let center = notes.iter().map(|&n| n as u32).sum::<u32>() / notes.len() as u32;
+                center.abs_diff(middle)
+            })
+            .collect();
+        let mut back: Vec<Vec<usize>> = vec![vec![0; cost.len()]];
+        for pair in chords.windows(2) {
+            let (previous, current) = (&pair[0].1, &pair[1].1);
+            let mut next_cost = vec![];
+            let mut from = vec![];
+            for notes in current {
+                let (best, j) = previous
+                    .iter()
+                    .enumerate()
+                    .map(|(j, prev)| (cost[j] + movement(prev, notes), j))
+                    .min()
+                    .unwrap();
+                next_cost.push(best);
+                from.push(j);
+            }
+            cost = next_cost;
+            back.push(from);
+        }
+        let mut choice = (0..cost.len()).min_by_key(|&k| cost[k]).unwrap();
+        let mut choices = vec![0; chords.len()];
+        for i in (0..chords.len()).rev() {
+            choices[i] = choice;
+            choice = back[i][choice];
+        }
+
+        chords
+            .iter()
+            .zip(choices)
+            .map(|((i, candidates), choice)| {
+                let timed = &timeline[*i];
+                Voicing {
+                    chord: timed.chord.clone(),
+                    bar: timed.bar,
+                    beat: timed.beat,
+                    notes: candidates[choice].clone(),
+                }
+            })
+            .collect()
+    }
+}